@@ -1,12 +1,17 @@
-//! This build script copies the `memory.x` file from the crate root into
+//! This build script copies the memory layout from the crate root into
 //! a directory where the linker can always find it at build time.
 //! For many projects this is optional, as the linker always searches the
 //! project root directory -- wherever `Cargo.toml` is. However, if you
 //! are using a workspace or have a more complicated build setup, this
 //! build script becomes required. Additionally, by requesting that
-//! Cargo re-run the build script whenever `memory.x` is changed,
-//! updating `memory.x` ensures a rebuild of the application with the
+//! Cargo re-run the build script whenever the layout is changed,
+//! updating it ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! The `boot_sram`, `boot_qspi` and `bootloader` features replace the
+//! default `memory.x` with the matching layout. The layouts live in
+//! `layouts/` rather than the crate root, where the linker would find the
+//! default `memory.x` before the one written here.
 
 use std::env;
 use std::fs::File;
@@ -16,18 +21,38 @@ use std::path::PathBuf;
 fn main() {
     println!("cargo::rustc-link-arg-tests=-Tembedded-test.x");
 
-    // Put `memory.x` in our output directory and ensure it's
+    let memory: &[u8] = match (
+        env::var_os("CARGO_FEATURE_BOOT_SRAM").is_some(),
+        env::var_os("CARGO_FEATURE_BOOT_QSPI").is_some(),
+        env::var_os("CARGO_FEATURE_BOOTLOADER").is_some(),
+    ) {
+        (true, false, false) => include_bytes!("layouts/memory_boot_sram.x"),
+        (false, true, false) => include_bytes!("layouts/memory_boot_qspi.x"),
+        (false, false, true) => include_bytes!("layouts/memory_bootloader.x"),
+        // several at once are reported by a `compile_error!` in the crate
+        _ => include_bytes!("layouts/memory.x"),
+    };
+
+    // Put the layout in our output directory as `memory.x` and ensure it's
     // on the linker search path.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory)
+        .unwrap();
+    File::create(out.join("daisy_sections.x"))
+        .unwrap()
+        .write_all(include_bytes!("layouts/daisy_sections.x"))
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
     // By default, Cargo will re-run a build script whenever
-    // any file in the project changes. By specifying `memory.x`
+    // any file in the project changes. By specifying the layouts
     // here, we ensure the build script is only re-run when
-    // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+    // one of them is changed.
+    println!("cargo:rerun-if-changed=layouts/memory.x");
+    println!("cargo:rerun-if-changed=layouts/memory_boot_sram.x");
+    println!("cargo:rerun-if-changed=layouts/memory_boot_qspi.x");
+    println!("cargo:rerun-if-changed=layouts/memory_bootloader.x");
+    println!("cargo:rerun-if-changed=layouts/daisy_sections.x");
 }
@@ -0,0 +1,44 @@
+//! Boot stage for applications stored in the QSPI flash.
+//!
+//! Flash this to the internal flash. It starts the image selected by
+//! `daisy_embassy::update` from the AXI SRAM, or blinks the user LED if
+//! there is no image to start. Applications load new images with
+//! `Updater::begin`, `write`, `finish` and `mark_pending`, and keep them
+//! with `Updater::confirm`.
+#![no_std]
+#![no_main]
+
+use daisy_embassy::new_daisy_board;
+use daisy_embassy::update::{boot, Layout, Updater};
+use defmt::{info, warn};
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let config = daisy_embassy::default_rcc();
+    let p = embassy_stm32::init(config);
+    let daisy_p = new_daisy_board!(p);
+    let mut led = daisy_p.user_led;
+
+    let mut updater = Updater::new(daisy_p.flash.build(), Layout::DEFAULT).unwrap();
+    info!("boot state: {}", updater.boot_state());
+    match boot::select(&mut updater) {
+        Ok(Some((slot, header))) => {
+            info!("starting slot {}: {}", slot, header);
+            // the boot stage only uses the DTCM, the AXI SRAM is free
+            unsafe { boot::start(&mut updater, slot, &header) }
+        }
+        Ok(None) => warn!("no image to start"),
+        Err(e) => warn!("could not read the boot state: {}", e),
+    }
+
+    loop {
+        led.on();
+        Timer::after_millis(100).await;
+        led.off();
+        Timer::after_millis(100).await;
+    }
+}
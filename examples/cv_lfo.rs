@@ -0,0 +1,36 @@
+//! Two LFOs on the DAC outputs: a 1 Hz triangle on D23 (DAC OUT 1) and a
+//! 0.25 Hz saw on D22 (DAC OUT 2), rendered in blocks like audio.
+#![no_std]
+#![no_main]
+
+use daisy_embassy::audio::Fs;
+use daisy_embassy::cv::CvOutputs;
+use daisy_embassy::{hal, new_daisy_board, DaisyBoard};
+use defmt::debug;
+use embassy_executor::Spawner;
+use {defmt_rtt as _, panic_probe as _};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    debug!("====program start====");
+    let p = hal::init(daisy_embassy::default_rcc());
+    let board: DaisyBoard<'_> = new_daisy_board!(p);
+
+    let cv = CvOutputs::new_with_dma(p.DAC1, p.DMA1_CH4, board.pins.d23, board.pins.d22);
+    let mut stream = cv.into_stream(p.TIM6, Fs::Fs48000);
+
+    let mut triangle = 0.0f32;
+    let mut saw = 0.0f32;
+    stream
+        .start(|out1, out2| {
+            for (a, b) in out1.iter_mut().zip(out2) {
+                triangle = (triangle + 1.0 / SAMPLE_RATE) % 1.0;
+                saw = (saw + 0.25 / SAMPLE_RATE) % 1.0;
+                *a = 1.0 - (2.0 * triangle - 1.0).abs();
+                *b = saw;
+            }
+        })
+        .await;
+}
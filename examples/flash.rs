@@ -1,6 +1,7 @@
 #![no_std]
 #![no_main]
 
+use daisy_embassy::flash::IS25LP064_JEDEC_ID;
 use daisy_embassy::new_daisy_board;
 use defmt::{error, info};
 use embassy_executor::Spawner;
@@ -20,6 +21,11 @@ async fn main(_spawner: Spawner) {
     let mut flash = daisy_p.flash.build();
 
     info!("uuid: {}", flash.read_uuid());
+    let jedec_id = flash.read_jedec_id();
+    info!("jedec id: {}", jedec_id);
+    if jedec_id != IS25LP064_JEDEC_ID {
+        error!("Unexpected flash part");
+    }
     // Create an array of data to write.
     let mut data: [u8; SIZE] = [0; SIZE];
     for (i, x) in data.iter_mut().enumerate() {
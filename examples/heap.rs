@@ -0,0 +1,41 @@
+//! Use `alloc` collections on a heap in the SDRAM.
+//! Run with `--features heap`.
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use daisy_embassy::new_daisy_board;
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(daisy_embassy::default_rcc());
+    let daisy_p = new_daisy_board!(p);
+    let mut core = cortex_m::Peripherals::take().unwrap();
+    let mut sdram = daisy_p.sdram.build(&mut core.MPU, &mut core.SCB);
+
+    // 16 MiB for the heap, the rest stays available for fixed buffers
+    defmt::unwrap!(sdram.init_heap(16 * 1024 * 1024));
+    info!("{} bytes of SDRAM left", sdram.available());
+
+    let mut values = Vec::new();
+    for i in 0..100_000u32 {
+        values.push(i);
+    }
+    let sum: u64 = values.iter().map(|&x| x as u64).sum();
+    info!("sum of {} values: {}", values.len(), sum);
+
+    let table: Box<[f32]> = (0..4096).map(|i| i as f32 / 4096.0).collect();
+    info!("last table entry: {}", table[table.len() - 1]);
+
+    loop {
+        Timer::after_millis(1000).await;
+    }
+}
@@ -0,0 +1,64 @@
+//! Audio passthrough with a volume knob on D15 and a balance knob on D16.
+//! Connect the wipers of two potentiometers between 3V3A and AGND.
+#![no_std]
+#![no_main]
+
+use daisy_embassy::adc::{AdcInputs, AdcValues};
+use daisy_embassy::hal::{self, adc::AdcChannel, peripherals};
+use daisy_embassy::{new_daisy_board, DaisyBoard};
+use defmt::debug;
+use embassy_executor::Spawner;
+use grounded::uninit::GroundedArrayCell;
+use {defmt_rtt as _, panic_probe as _};
+
+static KNOBS: AdcValues<2> = AdcValues::new();
+//DMA buffer must be in special region. Refer https://embassy.dev/book/#_stm32_bdma_only_working_out_of_some_ram_regions
+#[link_section = ".sram1_bss"]
+static READINGS: GroundedArrayCell<u16, { 2 * 4 }> = GroundedArrayCell::uninit();
+
+#[embassy_executor::task]
+async fn knobs(mut adc: AdcInputs<'static, peripherals::ADC1, peripherals::DMA1_CH3, 2>) {
+    adc.run().await;
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    debug!("====program start====");
+    let p = hal::init(daisy_embassy::default_rcc());
+    let board: DaisyBoard<'_> = new_daisy_board!(p);
+
+    let readings = unsafe {
+        READINGS.initialize_all_copied(0);
+        let (ptr, len) = READINGS.get_ptr_len();
+        core::slice::from_raw_parts_mut(ptr, len)
+    };
+    let channels = [board.pins.d15.degrade_adc(), board.pins.d16.degrade_adc()];
+    let adc = AdcInputs::new(
+        p.ADC1,
+        p.DMA1_CH3,
+        channels,
+        readings,
+        &KNOBS,
+        Default::default(),
+    );
+    spawner.spawn(knobs(adc)).unwrap();
+
+    let mut interface = board
+        .audio_peripherals
+        .prepare_interface(Default::default())
+        .await;
+
+    interface
+        .start(|input, output| {
+            let [volume, balance] = KNOBS.all();
+            let gains = [volume * (1.0 - balance) * 2.0, volume * balance * 2.0];
+            for (frame_in, frame_out) in input.chunks(2).zip(output.chunks_mut(2)) {
+                for ((sample_in, sample_out), gain) in frame_in.iter().zip(frame_out).zip(gains) {
+                    // 24 bit samples, sign extended to scale them
+                    let sample = ((*sample_in << 8) as i32 >> 8) as f32;
+                    *sample_out = ((sample * gain.min(1.0)) as i32) as u32;
+                }
+            }
+        })
+        .await;
+}
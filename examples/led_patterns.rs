@@ -0,0 +1,42 @@
+//! Show the status patterns of the user LED, switching every 5 seconds.
+#![no_std]
+#![no_main]
+
+use daisy_embassy::led::{Pattern, PatternPlayer, PwmLed};
+use daisy_embassy::new_daisy_board;
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Timer};
+
+use {defmt_rtt as _, panic_probe as _};
+
+static STATUS: PatternPlayer = PatternPlayer::new();
+
+#[embassy_executor::task]
+async fn status_led(led: PwmLed<'static>) {
+    STATUS.run(led).await;
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let p = embassy_stm32::init(daisy_embassy::default_rcc());
+    let daisy_p = new_daisy_board!(p, pwm_led);
+    spawner.spawn(status_led(daisy_p.user_led)).unwrap();
+
+    let patterns = [
+        Pattern::Blink {
+            on: Duration::from_millis(100),
+            off: Duration::from_millis(400),
+        },
+        Pattern::Breathe {
+            period: Duration::from_secs(2),
+        },
+        Pattern::Count(3),
+        Pattern::Morse("SOS"),
+    ];
+    for pattern in patterns.iter().cycle() {
+        info!("{}", pattern);
+        STATUS.play(*pattern);
+        Timer::after_secs(5).await;
+    }
+}
@@ -7,17 +7,13 @@
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use daisy_embassy::audio::Interface;
-use daisy_embassy::sdram::FmcDevice;
-use daisy_embassy::{audio::HALF_DMA_BUFFER_LENGTH, hal, new_daisy_board, sdram::SDRAM_SIZE};
+use daisy_embassy::buffer::AudioRingBuffer;
+use daisy_embassy::switch::Switch;
+use daisy_embassy::{hal, new_daisy_board};
 use defmt::{debug, info};
 use embassy_executor::{InterruptExecutor, Spawner};
-use embassy_stm32::fmc::Fmc;
 use embassy_stm32::interrupt;
 use embassy_stm32::interrupt::{InterruptExt, Priority};
-use embassy_stm32::peripherals::FMC;
-use embassy_stm32::{exti::ExtiInput, gpio::Pull};
-use embassy_time::{Delay, Timer};
-use stm32_fmc::Sdram;
 use {defmt_rtt as _, panic_probe as _};
 
 //take 48000(Hz) * 10(Sec) * 2(stereo)
@@ -32,53 +28,21 @@ unsafe fn SAI1() {
 }
 
 #[embassy_executor::task]
-async fn run_audio(
-    mut interface: Interface<'static>,
-    mut sdram: Sdram<Fmc<'static, FMC>, FmcDevice>,
-) {
-    let mut delay = Delay;
-    let ram_slice = unsafe {
-        // Initialise controller and SDRAM
-        let ram_ptr: *mut u32 = sdram.init(&mut delay) as *mut _;
-
-        // Convert raw pointer to slice
-        core::slice::from_raw_parts_mut(ram_ptr, SDRAM_SIZE / core::mem::size_of::<u32>())
-    };
-    let loop_buffer = &mut ram_slice[..LOOPER_LENGTH];
-    //clear loop_buffer
-    for smp in loop_buffer.iter_mut() {
-        *smp = SILENCE;
-    }
-
-    // Block Length
-    const BL: usize = HALF_DMA_BUFFER_LENGTH;
-    //record point
-    let mut rp = 0;
-    //playback point
-    let mut pp = 0;
+async fn run_audio(mut interface: Interface<'static>, mut looper: AudioRingBuffer<'static, u32>) {
     interface
         .start(|input, output| {
             // if triggered record, record incoming buffer till the loop buffer is full
             if RECORD.load(Ordering::SeqCst) {
-                let remain = BL.min(LOOPER_LENGTH - rp);
-                loop_buffer[rp..(rp + remain)].copy_from_slice(input);
-                rp += BL;
-                if rp >= LOOPER_LENGTH {
-                    rp = 0;
+                looper.write_block(input);
+                if looper.write_position() < input.len() {
+                    looper.seek_write(0);
                     RECORD.store(false, Ordering::SeqCst);
                     info!("finished recording");
                 }
             }
 
-            let remain = BL.min(LOOPER_LENGTH - pp);
-            let frac = BL - remain;
-            output.copy_from_slice(&loop_buffer[pp..(pp + remain)]);
-            if frac > 0 {
-                output[remain..BL].copy_from_slice(&loop_buffer[0..frac]);
-            }
-            pp += BL;
-            if pp >= LOOPER_LENGTH {
-                pp -= LOOPER_LENGTH;
+            looper.read_block(output);
+            if looper.read_position() < output.len() {
                 info!("loop!!");
             }
         })
@@ -96,20 +60,21 @@ async fn main(_spawner: Spawner) {
         .audio_peripherals
         .prepare_interface(Default::default())
         .await;
-    let sdram = board.sdram.build(&mut c.MPU, &mut c.SCB);
+    let mut sdram = board.sdram.build(&mut c.MPU, &mut c.SCB);
+    let loop_buffer = defmt::unwrap!(sdram.alloc_slice_filled(LOOPER_LENGTH, SILENCE));
+    let looper = AudioRingBuffer::new(loop_buffer);
 
-    let mut record_pin = ExtiInput::new(board.pins.d16, p.EXTI3, Pull::Up);
+    let mut record_button = Switch::new(board.pins.d16, p.EXTI3, Default::default());
     let record_fut = async {
         loop {
-            record_pin.wait_for_falling_edge().await;
+            record_button.wait_for_press().await;
             RECORD.store(true, Ordering::SeqCst);
             info!("record!!");
-            Timer::after_millis(15).await; // debounce
         }
     };
 
     interrupt::SAI1.set_priority(Priority::P6);
     let spawner = AUDIO_EXECUTOR.start(interrupt::SAI1);
-    defmt::unwrap!(spawner.spawn(run_audio(interface, sdram)));
+    defmt::unwrap!(spawner.spawn(run_audio(interface, looper)));
     record_fut.await;
 }
@@ -4,10 +4,9 @@
 #![no_main]
 
 use daisy_embassy::new_daisy_board;
-use daisy_embassy::sdram::SDRAM_SIZE;
 use defmt::info;
 use embassy_executor::Spawner;
-use embassy_time::{Delay, Timer};
+use embassy_time::Timer;
 
 use {defmt_rtt as _, panic_probe as _};
 
@@ -17,16 +16,9 @@ async fn main(_spawner: Spawner) {
     let daisy_p = new_daisy_board!(p);
     let mut core = cortex_m::Peripherals::take().unwrap();
     let mut sdram = daisy_p.sdram.build(&mut core.MPU, &mut core.SCB);
+    info!("{} bytes of SDRAM available", sdram.available());
 
-    let mut delay = Delay;
-
-    let ram_slice = unsafe {
-        // Initialise controller and SDRAM
-        let ram_ptr: *mut u32 = sdram.init(&mut delay) as *mut _;
-
-        // Convert raw pointer to slice
-        core::slice::from_raw_parts_mut(ram_ptr, SDRAM_SIZE / core::mem::size_of::<u32>())
-    };
+    let ram_slice: &mut [u32] = defmt::unwrap!(sdram.alloc_slice(1024));
 
     info!("RAM contents before writing: {:x}", ram_slice[..10]);
 
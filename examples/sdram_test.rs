@@ -0,0 +1,56 @@
+//! Check the SDRAM for assembly faults. A full march over 64 MiB takes a
+//! few seconds; the result is logged and shown on the user LED: steady on
+//! when everything passed, blinking otherwise.
+#![no_std]
+#![no_main]
+
+use daisy_embassy::new_daisy_board;
+use daisy_embassy::sdram::self_test::{Config, Test};
+use daisy_embassy::sdram::SDRAM_SIZE;
+use defmt::{error, info};
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+
+use {defmt_rtt as _, panic_probe as _};
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_stm32::init(daisy_embassy::default_rcc());
+    let daisy_p = new_daisy_board!(p);
+    let mut led = daisy_p.user_led;
+    let mut core = cortex_m::Peripherals::take().unwrap();
+    let mut sdram = daisy_p.sdram.build(&mut core.MPU, &mut core.SCB);
+
+    let report = sdram.self_test(&mut core.SCB, &Config::full(0..SDRAM_SIZE), |progress| {
+        if progress.test == Test::March {
+            info!("march: {}%", progress.done * 100 / progress.total);
+        } else {
+            info!("{} done", progress.test);
+        }
+    });
+
+    if report.passed() {
+        info!("SDRAM passed");
+        led.on();
+        loop {
+            Timer::after_millis(1000).await;
+        }
+    }
+
+    error!(
+        "{} failures, failing bits: {:032b}",
+        report.failure_count, report.failing_bits
+    );
+    for failure in report.failures() {
+        error!(
+            "{} at {:#x}: expected {:#010x}, read {:#010x}",
+            failure.test, failure.offset, failure.expected, failure.actual
+        );
+    }
+    loop {
+        led.on();
+        Timer::after_millis(100).await;
+        led.off();
+        Timer::after_millis(100).await;
+    }
+}
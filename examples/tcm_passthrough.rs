@@ -0,0 +1,67 @@
+//! Audio passthrough through a DC blocker running from the ITCM, with its
+//! state in the DTCM. Both are put there by `init_sections` before `main`.
+//!
+//! Works in the default and the `boot_sram` layout:
+//! cargo run --example tcm_passthrough --release --features boot_sram
+#![no_std]
+#![no_main]
+
+use daisy_embassy::{hal, new_daisy_board};
+use defmt::debug;
+use embassy_executor::Spawner;
+use {defmt_rtt as _, panic_probe as _};
+
+/// Pole of the DC blocker, about 10 Hz at 48 kHz.
+const POLE: f32 = 0.9987;
+
+#[derive(Clone, Copy)]
+struct DcBlocker {
+    x1: f32,
+    y1: f32,
+}
+
+daisy_embassy::dtcm! {
+    /// Left and right channel.
+    static mut STATE: [DcBlocker; 2] = [DcBlocker { x1: 0.0, y1: 0.0 }; 2];
+}
+
+daisy_embassy::itcm! {
+    /// One 24 bit sample through the DC blocker.
+    fn process(state: &mut DcBlocker, sample: u32) -> u32 {
+        // sign-extend the 24 bit sample
+        let x = ((sample << 8) as i32 >> 8) as f32;
+        let y = x - state.x1 + POLE * state.y1;
+        state.x1 = x;
+        state.y1 = y;
+        (y.clamp(-8_388_608.0, 8_388_607.0) as i32) as u32
+    }
+}
+
+#[cortex_m_rt::pre_init]
+unsafe fn pre_init() {
+    daisy_embassy::memory::init_sections();
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    debug!("====program start====");
+    let config = daisy_embassy::default_rcc();
+    let p = hal::init(config);
+    let board = new_daisy_board!(p);
+
+    let mut interface = board
+        .audio_peripherals
+        .prepare_interface(Default::default())
+        .await;
+
+    // SAFETY: only the audio callback uses the state
+    let state = unsafe { &mut *core::ptr::addr_of_mut!(STATE) };
+    interface
+        .start(|input, output| {
+            // interleaved left and right samples
+            for (i, (out, &sample)) in output.iter_mut().zip(input).enumerate() {
+                *out = process(&mut state[i % 2], sample);
+            }
+        })
+        .await;
+}
@@ -3,13 +3,11 @@
 
 use core::sync::atomic::{AtomicU8, Ordering};
 
+use daisy_embassy::switch::Switch;
 use daisy_embassy::{audio::HALF_DMA_BUFFER_LENGTH, hal, new_daisy_board};
 use defmt::debug;
 use embassy_executor::Spawner;
 use embassy_futures::join::join;
-use embassy_time::Timer;
-use hal::gpio::Pull;
-use hal::{exti::ExtiInput, gpio::Input};
 use {defmt_rtt as _, panic_probe as _};
 
 #[derive(Clone, Copy)]
@@ -50,20 +48,19 @@ async fn main(_spawner: Spawner) {
         .audio_peripherals
         .prepare_interface(Default::default())
         .await;
-    let mute = Input::new(board.pins.d15, Pull::Up);
-    let mut change_freq = ExtiInput::new(board.pins.d16, p.EXTI3, Pull::Up);
+    let mut mute = Switch::new(board.pins.d15, p.EXTI0, Default::default());
+    let mut change_freq = Switch::new(board.pins.d16, p.EXTI3, Default::default());
     let wave_freq = AtomicU8::new(0);
 
     let change_freq_fut = async {
         let mut local = 0;
         loop {
-            change_freq.wait_for_low().await;
+            change_freq.wait_for_press().await;
             local += 1;
             if local > 2 {
                 local = 0;
             }
             wave_freq.store(local, Ordering::SeqCst);
-            Timer::after_millis(30).await;
         }
     };
 
@@ -73,9 +70,10 @@ async fn main(_spawner: Spawner) {
         change_freq_fut,
         interface.start(|_input, output| {
             let period = WaveFrequency::from(wave_freq.load(Ordering::SeqCst)).as_period();
+            mute.update();
             for chunk in buf.chunks_mut(2) {
                 let smp = f32_to_u24(make_triangle_wave(smp_pos % period, period));
-                if mute.is_high() {
+                if !mute.is_pressed() {
                     chunk[0] = smp;
                     chunk[1] = smp;
                 } else {
@@ -125,6 +125,10 @@ async fn echo<'d, T: Instance + 'd>(
         let n = class.read_packet(&mut buf).await?;
         let data = &buf[..n];
         info!("data: {:x}", data);
+        // `echo dfu > /dev/ttyACM0` to flash the board with dfu-util
+        if data.trim_ascii() == b"dfu" {
+            daisy_embassy::system::reset_to_bootloader();
+        }
         class.write_packet(data).await?;
     }
 }
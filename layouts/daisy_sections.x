@@ -0,0 +1,79 @@
+/**
+ * Sections shared by all memory layouts, included from memory*.x.
+ */
+
+SECTIONS
+{
+    .sram1_bss (NOLOAD) :
+    {
+        . = ALIGN(4);
+        _ssram1_bss = .;
+
+        PROVIDE(__sram1_bss_start__ = _sram1_bss);
+        *(.sram1_bss)
+        *(.sram1_bss*)
+        . = ALIGN(4);
+        _esram1_bss = .;
+
+        PROVIDE(__sram1_bss_end__ = _esram1_bss);
+    } > RAM_D2
+
+    /* zeroed by `SdRamBuilder::build`, see `daisy_embassy::sdram_static!` */
+    .sdram_bss (NOLOAD) :
+    {
+        . = ALIGN(4);
+        _ssdram_bss = .;
+
+        PROVIDE(__sdram_bss_start = _ssdram_bss);
+        *(.sdram_bss)
+        *(.sdram_bss*)
+        . = ALIGN(4);
+        _esdram_bss = .;
+
+        PROVIDE(__sdram_bss_end = _esdram_bss);
+    } > SDRAM
+}
+
+/* Code and data copied to RAM by `daisy_embassy::memory::init_sections`. */
+SECTIONS
+{
+    /* skip the first word of the ITCM: address 0 is a null pointer to Rust */
+    .itcm_text ORIGIN(ITCMRAM) + 4 : ALIGN(4)
+    {
+        __sitcm_text = .;
+        *(.itcm_text .itcm_text.*)
+        . = ALIGN(4);
+        __eitcm_text = .;
+    } > ITCMRAM AT > FLASH
+    __siitcm_text = LOADADDR(.itcm_text);
+
+    .sram_text : ALIGN(4)
+    {
+        __ssram_text = .;
+        *(.sram_text .sram_text.*)
+        . = ALIGN(4);
+        __esram_text = .;
+    } > SRAM AT > FLASH
+    __sisram_text = LOADADDR(.sram_text);
+
+    .dtcm_data : ALIGN(4)
+    {
+        __sdtcm_data = .;
+        *(.dtcm_data .dtcm_data.*)
+        . = ALIGN(4);
+        __edtcm_data = .;
+    } > DTCMRAM AT > FLASH
+    __sidtcm_data = LOADADDR(.dtcm_data);
+} INSERT AFTER .data;
+
+/* Zeroed by `daisy_embassy::memory::init_sections`. */
+SECTIONS
+{
+    .dtcm_bss (NOLOAD) : ALIGN(4)
+    {
+        __sdtcm_bss = .;
+        *(.dtcm_bss .dtcm_bss.*)
+        . = ALIGN(4);
+        __edtcm_bss = .;
+    } > DTCMRAM
+} INSERT AFTER .bss;
@@ -2,6 +2,10 @@
  * See: https://github.com/electro-smith/libDaisy/blob/master/core/STM32H750IB_flash.lds
  *      https://github.com/stm32-rs/stm32h7xx-hal/blob/master/memory.x
  *      https://github.com/mtthw-meyer/libdaisy-rust/blob/master/memory.x
+ *
+ * Default layout: the program runs from the 128K internal flash.
+ * See memory_boot_sram.x and memory_boot_qspi.x for bigger programs, and
+ * memory_bootloader.x for a boot stage starting them.
  */
 
 /*ENTRY(Reset_Handler)*/
@@ -21,33 +25,4 @@ MEMORY
 /* stm32h7xx-hal uses a PROVIDE that expects RAM symbol to exist */
 REGION_ALIAS(RAM, DTCMRAM);
 
-SECTIONS
-{
-    .sram1_bss (NOLOAD) :
-    {
-        . = ALIGN(4);
-        _ssram1_bss = .;
-
-        PROVIDE(__sram1_bss_start__ = _sram1_bss);
-        *(.sram1_bss)
-        *(.sram1_bss*)
-        . = ALIGN(4);
-        _esram1_bss = .;
-
-        PROVIDE(__sram1_bss_end__ = _esram1_bss);
-    } > RAM_D2
-
-    .sdram_bss (NOLOAD) :
-    {
-        . = ALIGN(4);
-        _ssdram_bss = .;
-
-        PROVIDE(__sdram_bss_start = _ssdram_bss);
-        *(.sdram_bss)
-        *(.sdram_bss*)
-        . = ALIGN(4);
-        _esdram_bss = .;
-
-        PROVIDE(__sdram_bss_end = _esdram_bss);
-    } > SDRAM
-}
\ No newline at end of file
+INCLUDE daisy_sections.x
@@ -0,0 +1,24 @@
+/**
+ * See: https://github.com/electro-smith/libDaisy/blob/master/core/STM32H750IB_qspi.lds
+ *
+ * "BOOT_QSPI" layout, selected with the `boot_qspi` feature: the program runs
+ * in place from the memory-mapped QSPI flash, after the 256K reserved for the
+ * Daisy bootloader. Code is slow to fetch from there, so put hot functions in
+ * `.sram_text` or `.itcm_text`. The flash cannot be used through
+ * `daisy_embassy::flash` in this layout.
+ */
+
+MEMORY
+{
+    FLASH     (RX)  : ORIGIN = 0x90040000, LENGTH = 7936K
+    DTCMRAM   (RWX) : ORIGIN = 0x20000000, LENGTH = 128K
+    SRAM      (RWX) : ORIGIN = 0x24000000, LENGTH = 512K
+    RAM_D2    (RWX) : ORIGIN = 0x30000000, LENGTH = 288K
+    RAM_D3    (RWX) : ORIGIN = 0x38000000, LENGTH = 64K
+    ITCMRAM   (RWX) : ORIGIN = 0x00000000, LENGTH = 64K
+    SDRAM     (RWX) : ORIGIN = 0xc0000000, LENGTH = 64M
+}
+
+REGION_ALIAS(RAM, DTCMRAM);
+
+INCLUDE daisy_sections.x
@@ -0,0 +1,24 @@
+/**
+ * See: https://github.com/electro-smith/libDaisy/blob/master/core/STM32H750IB_sram.lds
+ *
+ * "BOOT_SRAM" layout, selected with the `boot_sram` feature: the program is
+ * stored in the QSPI flash and copied to the AXI SRAM by a boot stage (the
+ * Daisy bootloader or `daisy_embassy::update::boot`) before it is started.
+ * The last 32K of the AXI SRAM are left for `.sram_text` and other data.
+ */
+
+MEMORY
+{
+    FLASH     (RWX) : ORIGIN = 0x24000000, LENGTH = 480K
+    DTCMRAM   (RWX) : ORIGIN = 0x20000000, LENGTH = 128K
+    SRAM      (RWX) : ORIGIN = 0x24078000, LENGTH = 32K
+    RAM_D2    (RWX) : ORIGIN = 0x30000000, LENGTH = 288K
+    RAM_D3    (RWX) : ORIGIN = 0x38000000, LENGTH = 64K
+    ITCMRAM   (RWX) : ORIGIN = 0x00000000, LENGTH = 64K
+    SDRAM     (RWX) : ORIGIN = 0xc0000000, LENGTH = 64M
+    QSPIFLASH (RX)  : ORIGIN = 0x90000000, LENGTH = 8M
+}
+
+REGION_ALIAS(RAM, DTCMRAM);
+
+INCLUDE daisy_sections.x
@@ -0,0 +1,25 @@
+/**
+ * See: https://github.com/electro-smith/libDaisy/blob/master/core/STM32H750IB_flash.lds
+ *
+ * "BOOTLOADER" layout, selected with the `bootloader` feature: like the
+ * default layout, the program runs from the 128K internal flash, but the
+ * first 480K of the AXI SRAM are kept free for the image it loads to
+ * `daisy_embassy::update::SRAM_LOAD_ADDRESS`. `SRAM` only covers the last
+ * 32K, as in the BOOT_SRAM layout.
+ */
+
+MEMORY
+{
+    FLASH     (RX)  : ORIGIN = 0x08000000, LENGTH = 128K
+    DTCMRAM   (RWX) : ORIGIN = 0x20000000, LENGTH = 128K
+    SRAM      (RWX) : ORIGIN = 0x24078000, LENGTH = 32K
+    RAM_D2    (RWX) : ORIGIN = 0x30000000, LENGTH = 288K
+    RAM_D3    (RWX) : ORIGIN = 0x38000000, LENGTH = 64K
+    ITCMRAM   (RWX) : ORIGIN = 0x00000000, LENGTH = 64K
+    SDRAM     (RWX) : ORIGIN = 0xc0000000, LENGTH = 64M
+    QSPIFLASH (RX)  : ORIGIN = 0x90000000, LENGTH = 8M
+}
+
+REGION_ALIAS(RAM, DTCMRAM);
+
+INCLUDE daisy_sections.x
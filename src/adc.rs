@@ -0,0 +1,270 @@
+//! Knob and CV inputs on the ADC pins of the Seed.
+//!
+//! D15–D25 and D28 are all connected to ADC1, most of them to ADC2 as
+//! well. [`AdcInputs`] scans a chosen subset of them with DMA, averages
+//! several scans, smooths every channel and publishes the result in an
+//! [`AdcValues`], which the audio callback reads without locking:
+//!
+//! ```ignore
+//! use embassy_stm32::adc::AdcChannel;
+//!
+//! static KNOBS: AdcValues<2> = AdcValues::new();
+//! //DMA buffer must be in special region. Refer https://embassy.dev/book/#_stm32_bdma_only_working_out_of_some_ram_regions
+//! #[link_section = ".sram1_bss"]
+//! static READINGS: GroundedArrayCell<u16, { 2 * 4 }> = GroundedArrayCell::uninit();
+//!
+//! let readings = unsafe {
+//!     READINGS.initialize_all_copied(0);
+//!     let (ptr, len) = READINGS.get_ptr_len();
+//!     core::slice::from_raw_parts_mut(ptr, len)
+//! };
+//! let channels = [board.pins.d15.degrade_adc(), board.pins.d16.degrade_adc()];
+//! let mut adc = AdcInputs::new(p.ADC1, p.DMA1_CH3, channels, readings, &KNOBS, Default::default());
+//! // in a task
+//! adc.run().await;
+//! // in the audio callback
+//! let cutoff = KNOBS.get(0);
+//! ```
+//!
+//! Every `AdcInputs` has its own DMA buffer, so ADC1 and ADC2 can be used at
+//! the same time with different DMA streams.
+//!
+//! The ADC scans the channels in continuous mode, and a circular DMA
+//! transfer keeps the last [`AdcConfig::oversampling`] scans in the buffer
+//! without any CPU involvement. Every [`AdcConfig::interval`],
+//! [`AdcInputs::run`] averages the scans in the buffer, smooths them and
+//! publishes the result, so the values do not change within an audio block.
+//! A conversion takes the sample time plus 8.5 ADC clock cycles, about 20µs
+//! with the default [`SampleTime::CYCLES810_5`], which suits knobs and the
+//! high source impedance of a potentiometer.
+//!
+//! The ADC is clocked from PLL2_P, see [`default_rcc`](crate::default_rcc).
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_stm32 as hal;
+use embassy_time::{Duration, Ticker};
+use hal::adc::{Adc, AnyAdcChannel, Instance, Resolution, RxDma, SampleTime};
+use hal::dma::{Transfer, TransferOptions};
+use hal::pac::adc::vals::{Dmngt, Ovrmod};
+use hal::peripherals::{ADC1, ADC2};
+use hal::Peripheral;
+
+/// Conversions in one scan, the length of the ADC sequence.
+pub const MAX_CHANNELS: usize = 16;
+const FULL_SCALE: f32 = u16::MAX as f32;
+
+/// Latest values of the inputs, normalised to `0.0..=1.0`.
+pub struct AdcValues<const N: usize> {
+    values: [AtomicU32; N],
+}
+
+impl<const N: usize> AdcValues<N> {
+    pub const fn new() -> Self {
+        Self {
+            values: [const { AtomicU32::new(0) }; N],
+        }
+    }
+
+    /// Value of the input at `index` in the channel list.
+    pub fn get(&self, index: usize) -> f32 {
+        f32::from_bits(self.values[index].load(Ordering::Relaxed))
+    }
+
+    pub fn all(&self) -> [f32; N] {
+        core::array::from_fn(|index| self.get(index))
+    }
+
+    fn set(&self, index: usize, value: f32) {
+        self.values[index].store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl<const N: usize> Default for AdcValues<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdcConfig {
+    pub sample_time: SampleTime,
+    /// Scans kept in the DMA buffer and averaged into one update, at
+    /// least 1.
+    pub oversampling: u16,
+    /// Initial one-pole smoothing of every channel, from 0.0 (none) to
+    /// just below 1.0 (very slow), see [`AdcInputs::set_smoothing`].
+    pub smoothing: f32,
+    /// Time between two updates of the values.
+    pub interval: Duration,
+}
+
+impl Default for AdcConfig {
+    fn default() -> Self {
+        AdcConfig {
+            sample_time: SampleTime::CYCLES810_5,
+            oversampling: 4,
+            smoothing: 0.9,
+            interval: Duration::from_millis(1),
+        }
+    }
+}
+
+/// ADCs [`AdcInputs`] can run in continuous mode, ADC1 and ADC2.
+pub trait ScanAdc: Instance {
+    #[doc(hidden)]
+    fn regs() -> hal::pac::adc::Adc;
+}
+
+impl ScanAdc for ADC1 {
+    fn regs() -> hal::pac::adc::Adc {
+        hal::pac::ADC1
+    }
+}
+
+impl ScanAdc for ADC2 {
+    fn regs() -> hal::pac::adc::Adc {
+        hal::pac::ADC2
+    }
+}
+
+pub struct AdcInputs<'d, T: ScanAdc, D: RxDma<T>, const N: usize> {
+    adc: Adc<'d, T>,
+    dma: D,
+    channels: [AnyAdcChannel<T>; N],
+    readings: &'d mut [u16],
+    values: &'d AdcValues<N>,
+    smoothing: [f32; N],
+    state: [f32; N],
+    config: AdcConfig,
+}
+
+impl<'d, T: ScanAdc, D: RxDma<T>, const N: usize> AdcInputs<'d, T, D, N> {
+    /// `readings` is the circular DMA buffer, with room for
+    /// [`AdcConfig::oversampling`] scans of one reading per channel. It must
+    /// be in memory the DMA can reach, such as `.sram1_bss`; the DTCM is
+    /// not.
+    ///
+    /// # Panics
+    ///
+    /// If there are more than [`MAX_CHANNELS`] channels, or `readings` is
+    /// too short for the scans.
+    pub fn new(
+        adc: impl Peripheral<P = T> + 'd,
+        dma: D,
+        channels: [AnyAdcChannel<T>; N],
+        readings: &'d mut [u16],
+        values: &'d AdcValues<N>,
+        config: AdcConfig,
+    ) -> Self {
+        assert!(N <= MAX_CHANNELS, "an ADC scans up to 16 channels");
+        let len = N * config.oversampling.max(1) as usize;
+        assert!(
+            readings.len() >= len,
+            "one reading per channel and scan is needed"
+        );
+        let readings = &mut readings[..len];
+
+        let mut adc = Adc::new(adc);
+        adc.set_resolution(Resolution::BITS16);
+        Self {
+            adc,
+            dma,
+            channels,
+            readings,
+            values,
+            smoothing: [config.smoothing; N],
+            state: [0.0; N],
+            config,
+        }
+    }
+
+    /// Set the one-pole smoothing of input `index`: every update moves the
+    /// value by `1.0 - smoothing` of the way to the new reading.
+    pub fn set_smoothing(&mut self, index: usize, smoothing: f32) {
+        self.smoothing[index] = smoothing.clamp(0.0, 0.999);
+    }
+
+    /// Start the continuous scan and update the values every
+    /// [`AdcConfig::interval`], forever.
+    pub async fn run(&mut self) -> ! {
+        let Self {
+            adc,
+            dma,
+            channels,
+            readings,
+            values,
+            smoothing,
+            state,
+            config,
+        } = self;
+
+        // A single scan through the HAL sets up the channels and the
+        // sequence. Copied to every scan of the buffer, it is the starting
+        // point of the values instead of a ramp up from 0.
+        let sample_time = config.sample_time;
+        let sequence = channels.iter_mut().map(|channel| (channel, sample_time));
+        adc.read(dma, sequence, &mut readings[..N]).await;
+        for index in N..readings.len() {
+            readings[index] = readings[index % N];
+        }
+        let readings: *mut [u16] = *readings;
+        // SAFETY: from here on, the buffer is only accessed through the
+        // pointer
+        *state = unsafe { average(readings) };
+        for (index, value) in state.iter().enumerate() {
+            values.set(index, *value);
+        }
+
+        let regs = T::regs();
+        regs.cfgr().modify(|w| {
+            w.set_cont(true);
+            w.set_dmngt(Dmngt::DMA_CIRCULAR);
+            w.set_ovrmod(Ovrmod::OVERWRITE);
+        });
+        let request = dma.request();
+        let mut options = TransferOptions::default();
+        options.circular = true;
+        options.half_transfer_ir = false;
+        options.complete_transfer_ir = false;
+        // SAFETY: the transfer stops before `readings` is given back, when
+        // this future is dropped
+        let _transfer = unsafe {
+            Transfer::new_read_raw(
+                dma,
+                request,
+                regs.dr().as_ptr() as *mut u16,
+                readings,
+                options,
+            )
+        };
+        regs.cr().modify(|w| w.set_adstart(true));
+
+        let mut ticker = Ticker::every(config.interval);
+        loop {
+            ticker.next().await;
+            // SAFETY: the DMA only writes whole readings
+            let averages: [f32; N] = unsafe { average(readings) };
+            for (index, reading) in averages.into_iter().enumerate() {
+                let state = &mut state[index];
+                *state = reading + (*state - reading) * smoothing[index];
+                values.set(index, *state);
+            }
+        }
+    }
+}
+
+/// Average of each channel over the scans in `readings`, normalised.
+///
+/// # Safety
+///
+/// `readings` must be valid for reads, and may only be written by the DMA
+/// meanwhile.
+unsafe fn average<const N: usize>(readings: *const [u16]) -> [f32; N] {
+    let mut sums = [0u32; N];
+    let start = readings as *const u16;
+    for index in 0..readings.len() {
+        sums[index % N] += core::ptr::read_volatile(start.add(index)) as u32;
+    }
+    let scans = (readings.len() / N) as f32;
+    sums.map(|sum| sum as f32 / (scans * FULL_SCALE))
+}
@@ -301,18 +301,32 @@ pub enum Fs {
 }
 const CLOCK_RATIO: u32 = 256; //Not yet support oversampling.
 impl Fs {
-    pub fn into_clock_divider(self) -> MasterClockDivider {
-        let fs = match self {
+    pub fn into_hz(self) -> u32 {
+        match self {
             Fs::Fs8000 => 8000,
             Fs::Fs32000 => 32000,
             Fs::Fs44100 => 44100,
             Fs::Fs48000 => 48000,
             Fs::Fs88200 => 88200,
             Fs::Fs96000 => 96000,
-        };
+        }
+    }
+
+    pub fn into_clock_divider(self) -> MasterClockDivider {
+        mclk_div_from_u8(self.mclk_div() as u8)
+    }
+
+    /// Rate the SAI really runs at. The master clock divider is a whole
+    /// number, so this is only close to [`Fs::into_hz`], e.g. 48014 Hz for
+    /// `Fs48000` with [`default_rcc`](crate::default_rcc).
+    pub fn actual_hz(self) -> f32 {
+        let kernel_clock = hal::rcc::frequency::<hal::peripherals::SAI1>().0;
+        kernel_clock as f32 / (self.mclk_div() * CLOCK_RATIO) as f32
+    }
+
+    fn mclk_div(self) -> u32 {
         let kernel_clock = hal::rcc::frequency::<hal::peripherals::SAI1>().0;
-        let mclk_div = (kernel_clock / (fs * CLOCK_RATIO)) as u8;
-        mclk_div_from_u8(mclk_div)
+        kernel_clock / (self.into_hz() * CLOCK_RATIO)
     }
 }
 
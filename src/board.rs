@@ -1,15 +1,17 @@
 use crate::flash::FlashBuilder;
 use crate::led::UserLed;
 use crate::pins::*;
+use crate::switch::BootButton;
 use crate::usb::UsbPeripherals;
 use crate::{audio::AudioPeripherals, sdram::SdRamBuilder};
-pub struct DaisyBoard<'a> {
+pub struct DaisyBoard<'a, Led = UserLed<'a>> {
     pub pins: DaisyPins,
-    pub user_led: UserLed<'a>,
+    // a `PwmLed` with `new_daisy_board!(p, pwm_led)`
+    pub user_led: Led,
     pub audio_peripherals: AudioPeripherals,
     pub flash: FlashBuilder,
     pub sdram: SdRamBuilder,
     pub usb_peripherals: UsbPeripherals,
     // on board "BOOT" button.
-    pub boot: Boot,
+    pub boot: BootButton<'a>,
 }
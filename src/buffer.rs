@@ -0,0 +1,264 @@
+//! Delay lines and ring buffers for audio, over any `&mut [T]`, e.g. a
+//! buffer allocated in the SDRAM with [`SdRam`](crate::sdram::SdRam).
+//!
+//! Both work sample by sample or on whole blocks like the
+//! [`HALF_DMA_BUFFER_LENGTH`](crate::audio::HALF_DMA_BUFFER_LENGTH) blocks
+//! of the audio callback. `T` can be a single sample or a frame such as
+//! `[f32; 2]`.
+
+/// A delay line. Reads look back from the most recently written sample,
+/// so any number of taps can be read after each write.
+///
+/// ```ignore
+/// let mut delay = DelayLine::new(sdram.alloc_slice::<f32>(48_000)?);
+/// // in the audio callback
+/// delay.write(input);
+/// let echo = delay.read(12_000) * 0.5 + delay.read_linear(7_431.5) * 0.25;
+/// ```
+pub struct DelayLine<'a, T> {
+    buffer: &'a mut [T],
+    /// Index of the most recently written sample.
+    head: usize,
+}
+
+impl<'a, T: Copy> DelayLine<'a, T> {
+    /// # Panics
+    ///
+    /// If `buffer` is empty.
+    pub fn new(buffer: &'a mut [T]) -> Self {
+        assert!(!buffer.is_empty(), "delay line buffer must not be empty");
+        Self { buffer, head: 0 }
+    }
+
+    /// Longest delay that can be read.
+    pub fn max_delay(&self) -> usize {
+        self.buffer.len() - 1
+    }
+
+    /// Fill the whole line with `value`, e.g. silence.
+    pub fn fill(&mut self, value: T) {
+        self.buffer.fill(value);
+    }
+
+    pub fn write(&mut self, value: T) {
+        self.head = (self.head + 1) % self.buffer.len();
+        self.buffer[self.head] = value;
+    }
+
+    /// The sample written `delay` samples before the most recent one,
+    /// which is at delay 0. Delays are clamped to [`DelayLine::max_delay`].
+    pub fn read(&self, delay: usize) -> T {
+        let delay = delay.min(self.max_delay());
+        let len = self.buffer.len();
+        self.buffer[(self.head + len - delay) % len]
+    }
+
+    /// Write a whole block, oldest sample first.
+    pub fn write_block(&mut self, block: &[T]) {
+        let len = self.buffer.len();
+        // only the newest samples fit if the block is longer than the line
+        let block = &block[block.len().saturating_sub(len)..];
+        let start = (self.head + 1) % len;
+        let first = block.len().min(len - start);
+        self.buffer[start..start + first].copy_from_slice(&block[..first]);
+        self.buffer[..block.len() - first].copy_from_slice(&block[first..]);
+        self.head = (self.head + block.len()) % len;
+    }
+
+    /// Read a block delayed by `delay`: `output[i]` is what was written
+    /// `delay` samples before the `i`th sample of the last written block of
+    /// `output.len()` samples.
+    pub fn read_block(&self, delay: usize, output: &mut [T]) {
+        let n = output.len();
+        for (i, out) in output.iter_mut().enumerate() {
+            *out = self.read(delay + (n - 1 - i));
+        }
+    }
+}
+
+impl DelayLine<'_, f32> {
+    /// Read between samples, interpolating linearly.
+    pub fn read_linear(&self, delay: f32) -> f32 {
+        let (index, frac) = self.split(delay);
+        let a = self.read(index);
+        let b = self.read(index + 1);
+        a + (b - a) * frac
+    }
+
+    /// Read between samples with 4-point Hermite interpolation, smoother
+    /// than [`DelayLine::read_linear`] for modulated delays.
+    pub fn read_cubic(&self, delay: f32) -> f32 {
+        let (index, frac) = self.split(delay);
+        let ym1 = self.read(index.saturating_sub(1));
+        let y0 = self.read(index);
+        let y1 = self.read(index + 1);
+        let y2 = self.read(index + 2);
+        let c1 = 0.5 * (y1 - ym1);
+        let c2 = ym1 - 2.5 * y0 + 2.0 * y1 - 0.5 * y2;
+        let c3 = 0.5 * (y2 - ym1) + 1.5 * (y0 - y1);
+        ((c3 * frac + c2) * frac + c1) * frac + y0
+    }
+
+    /// Whole and fractional part of a delay clamped to the line.
+    fn split(&self, delay: f32) -> (usize, f32) {
+        let delay = delay.clamp(0.0, self.max_delay() as f32);
+        // truncation is floor for positive values
+        let index = delay as usize;
+        (index, delay - index as f32)
+    }
+}
+
+/// A ring buffer with independent read and write positions that wrap
+/// around, e.g. for loopers or to pass audio between tasks.
+///
+/// ```ignore
+/// let mut ring = AudioRingBuffer::new(sdram.alloc_slice::<u32>(LOOP_LENGTH)?);
+/// // in the audio callback
+/// if recording {
+///     ring.write_block(input);
+/// }
+/// ring.read_block(output);
+/// ```
+pub struct AudioRingBuffer<'a, T> {
+    buffer: &'a mut [T],
+    read: usize,
+    write: usize,
+}
+
+impl<'a, T: Copy> AudioRingBuffer<'a, T> {
+    /// # Panics
+    ///
+    /// If `buffer` is empty.
+    pub fn new(buffer: &'a mut [T]) -> Self {
+        assert!(!buffer.is_empty(), "ring buffer must not be empty");
+        Self {
+            buffer,
+            read: 0,
+            write: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn fill(&mut self, value: T) {
+        self.buffer.fill(value);
+    }
+
+    pub fn read_position(&self) -> usize {
+        self.read
+    }
+
+    pub fn write_position(&self) -> usize {
+        self.write
+    }
+
+    /// Move the read position, wrapping around.
+    pub fn seek_read(&mut self, position: usize) {
+        self.read = position % self.buffer.len();
+    }
+
+    /// Move the write position, wrapping around.
+    pub fn seek_write(&mut self, position: usize) {
+        self.write = position % self.buffer.len();
+    }
+
+    /// Samples from the read to the write position, i.e. written but not
+    /// read yet when used as a FIFO.
+    pub fn available(&self) -> usize {
+        (self.write + self.buffer.len() - self.read) % self.buffer.len()
+    }
+
+    /// Copy `block` in at the write position and advance it.
+    pub fn write_block(&mut self, block: &[T]) {
+        let mut rest = block;
+        while !rest.is_empty() {
+            let n = rest.len().min(self.buffer.len() - self.write);
+            self.buffer[self.write..self.write + n].copy_from_slice(&rest[..n]);
+            self.write = (self.write + n) % self.buffer.len();
+            rest = &rest[n..];
+        }
+    }
+
+    /// Fill `output` from the read position and advance it.
+    pub fn read_block(&mut self, output: &mut [T]) {
+        let mut rest = output;
+        while !rest.is_empty() {
+            let n = rest.len().min(self.buffer.len() - self.read);
+            let (head, tail) = rest.split_at_mut(n);
+            head.copy_from_slice(&self.buffer[self.read..self.read + n]);
+            self.read = (self.read + n) % self.buffer.len();
+            rest = tail;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_line_wraps_around() {
+        let mut buffer = [0.0f32; 8];
+        let mut delay = DelayLine::new(&mut buffer);
+        for i in 1..=20 {
+            delay.write(i as f32);
+        }
+        assert_eq!(delay.read(0), 20.0);
+        assert_eq!(delay.read(7), 13.0);
+        // clamped to the longest delay
+        assert_eq!(delay.read(100), 13.0);
+
+        delay.write_block(&[21.0, 22.0, 23.0]);
+        let mut block = [0.0; 3];
+        delay.read_block(2, &mut block);
+        assert_eq!(block, [19.0, 20.0, 21.0]);
+
+        // a block longer than the line keeps its newest samples
+        let long: [f32; 11] = core::array::from_fn(|i| 30.0 + i as f32);
+        delay.write_block(&long);
+        assert_eq!(delay.read(0), 40.0);
+        assert_eq!(delay.read(7), 33.0);
+    }
+
+    #[test]
+    fn delay_line_interpolates_between_samples() {
+        let mut buffer = [0.0f32; 8];
+        let mut delay = DelayLine::new(&mut buffer);
+        for i in 1..=20 {
+            delay.write(i as f32);
+        }
+        assert_eq!(delay.read_linear(1.5), 18.5);
+        assert_eq!(delay.read_linear(2.0), 18.0);
+        assert_eq!(delay.read_linear(-1.0), 20.0);
+        // a ramp is reproduced exactly by the cubic as well
+        assert!((delay.read_cubic(2.25) - 17.75).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ring_buffer_wraps_blocks() {
+        let mut buffer = [0u32; 10];
+        let mut ring = AudioRingBuffer::new(&mut buffer);
+        let mut block = [0; 4];
+
+        ring.write_block(&[1, 2, 3, 4, 5, 6, 7]);
+        ring.read_block(&mut block);
+        assert_eq!(block, [1, 2, 3, 4]);
+        ring.write_block(&[8, 9, 10, 11, 12]);
+        assert_eq!(ring.write_position(), 2);
+        assert_eq!(ring.available(), 8);
+        ring.read_block(&mut block);
+        ring.read_block(&mut block);
+        assert_eq!(block, [9, 10, 11, 12]);
+        assert_eq!(ring.read_position(), 2);
+        assert_eq!(ring.available(), 0);
+
+        ring.seek_read(13);
+        assert_eq!(ring.read_position(), 3);
+    }
+}
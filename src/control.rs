@@ -0,0 +1,314 @@
+//! Conditioning of knobs and other analog controls.
+//!
+//! Raw readings, e.g. from [`AdcValues`](crate::adc::AdcValues), jitter and
+//! step. An [`AnalogControl`] turns them into a usable parameter, in this
+//! order: [`Calibration`], invert, one-pole smoothing and slew limiting,
+//! hysteresis, then a [`Curve`].
+//!
+//! ```ignore
+//! let mut cutoff = AnalogControl::new(AnalogConfig {
+//!     curve: Curve::Exponential,
+//!     ..Default::default()
+//! });
+//! // in the audio callback, once per block
+//! let cutoff_hz = 20.0 + cutoff.update(KNOBS.get(0)) * 19_980.0;
+//! ```
+
+// on the host the std methods are used instead
+#[cfg(target_os = "none")]
+use micromath::F32Ext;
+
+/// Range of [`Curve::Logarithmic`] and [`Curve::Exponential`], 60 dB.
+const CURVE_RANGE: f32 = 1000.0;
+/// `ln(CURVE_RANGE)`
+const CURVE_RANGE_LN: f32 = 6.907_755;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Curve {
+    Linear,
+    /// Fast at the start, slow at the end, the inverse of
+    /// [`Curve::Exponential`].
+    Logarithmic,
+    /// Slow at the start, fast at the end, like an audio taper pot. Suits
+    /// gains and frequencies.
+    Exponential,
+}
+
+impl Curve {
+    /// Map `x` in `0.0..=1.0` onto the curve, keeping both ends.
+    pub fn apply(&self, x: f32) -> f32 {
+        // exact ends despite the approximated exp and ln
+        if x <= 0.0 {
+            return 0.0;
+        } else if x >= 1.0 {
+            return 1.0;
+        }
+        match self {
+            Curve::Linear => x,
+            Curve::Logarithmic => (1.0 + x * (CURVE_RANGE - 1.0)).ln() / CURVE_RANGE_LN,
+            Curve::Exponential => ((x * CURVE_RANGE_LN).exp() - 1.0) / (CURVE_RANGE - 1.0),
+        }
+    }
+}
+
+/// Linear correction of a reading, from two points measured on the
+/// hardware.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Calibration {
+    pub scale: f32,
+    pub offset: f32,
+}
+
+impl Calibration {
+    /// Size of [`Calibration::to_bytes`].
+    pub const SIZE: usize = 8;
+
+    pub const IDENTITY: Self = Self {
+        scale: 1.0,
+        offset: 0.0,
+    };
+
+    /// The calibration reading `raw_a` as `value_a` and `raw_b` as
+    /// `value_b`, e.g. a pot turned fully down and up:
+    /// `Calibration::from_points((0.004, 0.0), (0.991, 1.0))`.
+    ///
+    /// Returns `None` if both readings are the same.
+    pub fn from_points(a: (f32, f32), b: (f32, f32)) -> Option<Self> {
+        let ((raw_a, value_a), (raw_b, value_b)) = (a, b);
+        let scale = (value_b - value_a) / (raw_b - raw_a);
+        let calibration = Self {
+            scale,
+            offset: value_a - raw_a * scale,
+        };
+        calibration.is_valid().then_some(calibration)
+    }
+
+    pub fn apply(&self, raw: f32) -> f32 {
+        raw * self.scale + self.offset
+    }
+
+    /// Little-endian bytes, to store with
+    /// [`Settings`](crate::settings::Settings).
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..4].copy_from_slice(&self.scale.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.offset.to_le_bytes());
+        bytes
+    }
+
+    /// Read back [`Calibration::to_bytes`]. Returns `None` if the bytes do
+    /// not hold a usable calibration, e.g. erased flash.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; Self::SIZE] = bytes.try_into().ok()?;
+        let calibration = Self {
+            scale: f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            offset: f32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        };
+        calibration.is_valid().then_some(calibration)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.scale.is_finite() && self.scale != 0.0 && self.offset.is_finite()
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct AnalogConfig {
+    pub calibration: Calibration,
+    /// Turn the control around, e.g. for a pot wired the other way.
+    pub invert: bool,
+    /// One-pole smoothing, from 0.0 (none) to just below 1.0 (very slow).
+    pub smoothing: f32,
+    /// Largest change per update, to glide through jumps.
+    pub slew: Option<f32>,
+    /// Changes smaller than this are ignored, so a control left alone does
+    /// not flicker between two values.
+    pub hysteresis: f32,
+    pub curve: Curve,
+}
+
+impl Default for AnalogConfig {
+    fn default() -> Self {
+        AnalogConfig {
+            calibration: Calibration::IDENTITY,
+            invert: false,
+            smoothing: 0.5,
+            slew: None,
+            hysteresis: 0.002,
+            curve: Curve::Linear,
+        }
+    }
+}
+
+pub struct AnalogControl {
+    config: AnalogConfig,
+    smoothed: f32,
+    held: f32,
+    value: f32,
+    started: bool,
+}
+
+impl AnalogControl {
+    pub const fn new(config: AnalogConfig) -> Self {
+        Self {
+            config,
+            smoothed: 0.0,
+            held: 0.0,
+            value: 0.0,
+            started: false,
+        }
+    }
+
+    pub fn config(&self) -> &AnalogConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: AnalogConfig) {
+        self.config = config;
+        self.value = config.curve.apply(self.held);
+    }
+
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.config.calibration = calibration;
+    }
+
+    /// Feed a raw reading and return the new value in `0.0..=1.0`.
+    ///
+    /// The first reading is taken as it is, without smoothing or slew.
+    pub fn update(&mut self, raw: f32) -> f32 {
+        let mut x = self.config.calibration.apply(raw).clamp(0.0, 1.0);
+        if self.config.invert {
+            x = 1.0 - x;
+        }
+
+        if !self.started {
+            self.started = true;
+            self.smoothed = x;
+            self.held = x;
+        } else {
+            let mut next = x + (self.smoothed - x) * self.config.smoothing.clamp(0.0, 0.999);
+            if let Some(slew) = self.config.slew {
+                next = next.clamp(self.smoothed - slew, self.smoothed + slew);
+            }
+            self.smoothed = next;
+
+            let moved = (self.smoothed - self.held).abs() > self.config.hysteresis;
+            // always let the ends through, or they could not be reached
+            let at_end =
+                (self.smoothed <= 0.0 || self.smoothed >= 1.0) && self.smoothed != self.held;
+            if moved || at_end {
+                self.held = self.smoothed;
+            }
+        }
+
+        self.value = self.config.curve.apply(self.held);
+        self.value
+    }
+
+    /// The value returned by the last [`AnalogControl::update`].
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Forget the history, so the next reading is taken as it is.
+    pub fn reset(&mut self) {
+        self.started = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(config: AnalogConfig) -> AnalogControl {
+        AnalogControl::new(AnalogConfig {
+            smoothing: 0.0,
+            ..config
+        })
+    }
+
+    #[test]
+    fn hysteresis_holds_up_to_its_edge() {
+        let mut control = control(AnalogConfig {
+            hysteresis: 0.125,
+            ..Default::default()
+        });
+        assert_eq!(control.update(0.5), 0.5);
+        assert_eq!(control.update(0.625), 0.5);
+        assert_eq!(control.update(0.375), 0.5);
+        assert_eq!(control.update(0.6875), 0.6875);
+        assert_eq!(control.value(), 0.6875);
+
+        assert_eq!(control.update(0.9375), 0.9375);
+        // the ends get through even within the hysteresis
+        assert_eq!(control.update(1.0), 1.0);
+        assert_eq!(control.update(2.0), 1.0);
+        control.reset();
+        assert_eq!(control.update(0.0625), 0.0625);
+        assert_eq!(control.update(-1.0), 0.0);
+    }
+
+    #[test]
+    fn smooths_slews_and_inverts() {
+        let mut control = AnalogControl::new(AnalogConfig {
+            invert: true,
+            smoothing: 0.5,
+            slew: Some(0.125),
+            hysteresis: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(control.update(0.25), 0.75);
+        // halfway to 0.25, limited to 0.125 a step
+        assert_eq!(control.update(0.75), 0.625);
+        assert_eq!(control.update(0.75), 0.5);
+        assert_eq!(control.update(0.75), 0.375);
+        assert_eq!(control.update(0.75), 0.3125);
+    }
+
+    #[test]
+    fn curves_keep_their_ends() {
+        for curve in [Curve::Linear, Curve::Logarithmic, Curve::Exponential] {
+            assert_eq!(curve.apply(-0.5), 0.0);
+            assert_eq!(curve.apply(0.0), 0.0);
+            assert_eq!(curve.apply(1.0), 1.0);
+            assert_eq!(curve.apply(1.5), 1.0);
+        }
+        assert_eq!(Curve::Linear.apply(0.25), 0.25);
+        assert!(Curve::Exponential.apply(0.5) < 0.1);
+        assert!(Curve::Logarithmic.apply(0.5) > 0.85);
+        for x in [0.1, 0.5, 0.9] {
+            let y = Curve::Exponential.apply(Curve::Logarithmic.apply(x));
+            assert!((y - x).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn calibration_round_trips() {
+        let calibration = Calibration::from_points((0.25, 0.0), (0.75, 1.0)).unwrap();
+        assert_eq!(calibration.apply(0.25), 0.0);
+        assert_eq!(calibration.apply(0.75), 1.0);
+        assert_eq!(calibration.apply(0.5), 0.5);
+        assert_eq!(
+            Calibration::from_bytes(&calibration.to_bytes()),
+            Some(calibration)
+        );
+
+        assert_eq!(Calibration::from_points((0.5, 0.0), (0.5, 1.0)), None);
+        assert_eq!(Calibration::from_bytes(&[0xFF; 8]), None);
+        assert_eq!(Calibration::from_bytes(&[0; 8]), None);
+        assert_eq!(Calibration::from_bytes(&[0; 4]), None);
+
+        let mut control = control(AnalogConfig {
+            calibration,
+            ..Default::default()
+        });
+        assert_eq!(control.update(0.75), 1.0);
+        assert_eq!(control.update(0.25), 0.0);
+    }
+}
@@ -0,0 +1,266 @@
+//! CV outputs on the DAC pins, D23 (DAC OUT 1) and D22 (DAC OUT 2).
+//!
+//! [`CvOutputs`] sets the outputs directly, e.g. once per audio block from a
+//! task, or turns into a [`CvStream`] that plays blocks of samples through
+//! DMA at the audio sample rate. A stream block is [`BLOCK_LENGTH`] samples
+//! long like the blocks of the audio callback, so LFOs and envelopes can be
+//! rendered the same way. Written from the audio callback, the stream stays
+//! in step with the audio:
+//!
+//! ```ignore
+//! let cv = CvOutputs::new_with_dma(p.DAC1, p.DMA1_CH4, board.pins.d23, board.pins.d22);
+//! let mut stream = cv.into_stream(p.TIM6, Fs::Fs48000);
+//! interface
+//!     .start(|input, output| {
+//!         for (a, b) in lfo1.iter_mut().zip(&mut lfo2) {
+//!             phase = (phase + 1.0 / 48_000.0) % 1.0;
+//!             *a = phase; // saw LFO
+//!             *b = 1.0 - phase;
+//!         }
+//!         stream.write(&lfo1, &lfo2).ok();
+//!         // ... audio
+//!     })
+//!     .await;
+//! ```
+//!
+//! Both outputs are played by a single circular DMA transfer, refilled a
+//! block at a time as it plays. The DAC converts on the update events of
+//! TIM6. The timer runs from the APB clock rather than the audio PLL, so its
+//! period is set as close as it gets to the rate the SAI actually runs at
+//! (see [`Fs::actual_hz`]), a fraction of a tick off. [`CvStream::write`]
+//! nudges it every block to follow the audio, while [`CvStream::start`]
+//! runs on its own and drifts slowly against the audio interface.
+//!
+//! The outputs swing from 0V to 3.3V; Eurorack levels need an external
+//! amplifier.
+
+use crate::audio::{Fs, BLOCK_LENGTH};
+use core::cmp::Ordering;
+use defmt::error;
+use embassy_stm32 as hal;
+use grounded::uninit::GroundedArrayCell;
+use hal::dac::{DacCh1, DacCh2, DacDma1, TriggerSel, Value};
+use hal::dma::{NoDma, TransferOptions, WritableRingBuffer};
+use hal::pac::timer::vals::Mms;
+use hal::peripherals::{DAC1, PA4, PA5, TIM6};
+use hal::time::Hertz;
+use hal::timer::low_level::Timer;
+
+/// Output voltage at full scale.
+pub const DAC_VOLTS: f32 = 3.3;
+const FULL_SCALE: f32 = 4095.0;
+/// Blocks in the DMA buffer of a [`CvStream`]. [`CvStream::write`] keeps it
+/// half full, so the audio callback can be late or early by a block.
+const STREAM_BLOCKS: usize = 4;
+
+//DMA buffer must be in special region. Refer https://embassy.dev/book/#_stm32_bdma_only_working_out_of_some_ram_regions
+#[link_section = ".sram1_bss"]
+static STREAM_BUFFER: GroundedArrayCell<u32, { BLOCK_LENGTH * STREAM_BLOCKS }> =
+    GroundedArrayCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum CvOutput {
+    /// D23, PA4
+    Out1,
+    /// D22, PA5
+    Out2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// The DMA ran into data not written yet, or the buffer had no room for
+    /// a block, e.g. because blocks came too late or too early. The stream
+    /// starts over from the next block.
+    Overrun,
+}
+
+pub struct CvOutputs<'d, D = NoDma> {
+    out1: DacCh1<'d, DAC1, NoDma>,
+    out2: DacCh2<'d, DAC1, NoDma>,
+    dma: D,
+}
+
+impl<'d> CvOutputs<'d> {
+    pub fn new(dac: DAC1, out1: PA4, out2: PA5) -> Self {
+        Self::init(dac, NoDma, out1, out2)
+    }
+}
+
+impl<'d, D: DacDma1<DAC1>> CvOutputs<'d, D> {
+    /// Outputs that can be turned into a [`CvStream`]. `dma` feeds both
+    /// outputs and must not be used by the audio interface, which takes
+    /// DMA1_CH0 to DMA1_CH2.
+    pub fn new_with_dma(dac: DAC1, dma: D, out1: PA4, out2: PA5) -> Self {
+        Self::init(dac, dma, out1, out2)
+    }
+
+    /// Stream both outputs at the sample rate `fs`, which should match the
+    /// one of the audio interface to get a block per audio block. The SAI
+    /// must already be clocked, as its actual rate is used.
+    pub fn into_stream(self, tim: TIM6, fs: Fs) -> CvStream<'d> {
+        let Self {
+            mut out1,
+            mut out2,
+            dma,
+        } = self;
+        let timer = Timer::new(tim);
+        timer.set_frequency(Hertz(fs.into_hz()));
+        // the timer counts `0..=arr`, a period of `arr + 1` prescaled ticks
+        let prescaled =
+            timer.get_clock_frequency().0 as f32 / (timer.regs_basic().psc().read() as f32 + 1.0);
+        let period = (prescaled / fs.actual_hz() + 0.5) as u16 - 1;
+        timer.regs_basic().arr().write(|w| w.set_arr(period));
+        // update events trigger the conversions
+        timer.regs_basic().cr2().modify(|w| w.set_mms(Mms::UPDATE));
+        out1.set_trigger(TriggerSel::Tim6);
+        out1.set_triggering(true);
+        out1.enable();
+        out2.set_trigger(TriggerSel::Tim6);
+        out2.set_triggering(true);
+        out2.enable();
+        // the requests of channel 1 move both values at once through the
+        // dual channel register
+        hal::pac::DAC1.cr().modify(|w| w.set_dmaen(0, true));
+
+        let buffer = unsafe {
+            STREAM_BUFFER.initialize_all_copied(0);
+            let (ptr, len) = STREAM_BUFFER.get_ptr_len();
+            core::slice::from_raw_parts_mut(ptr, len)
+        };
+        let request = dma.request();
+        let ring = unsafe {
+            WritableRingBuffer::new(
+                dma,
+                request,
+                hal::pac::DAC1.dhr12rd().as_ptr() as *mut u32,
+                buffer,
+                TransferOptions::default(),
+            )
+        };
+        CvStream {
+            _out1: out1,
+            _out2: out2,
+            timer,
+            ring,
+            period,
+            running: false,
+        }
+    }
+}
+
+impl<'d, D> CvOutputs<'d, D> {
+    fn init(dac: DAC1, dma: D, out1: PA4, out2: PA5) -> Self {
+        let dac = hal::dac::Dac::new(dac, NoDma, NoDma, out1, out2);
+        let (mut out1, mut out2) = dac.split();
+        out1.set(Value::Bit12Right(0));
+        out2.set(Value::Bit12Right(0));
+        Self { out1, out2, dma }
+    }
+
+    /// Set `output` to `value` from 0.0 (0V) to 1.0 (3.3V).
+    pub fn set(&mut self, output: CvOutput, value: f32) {
+        self.set_raw(output, to_raw(value));
+    }
+
+    pub fn set_voltage(&mut self, output: CvOutput, volts: f32) {
+        self.set(output, volts / DAC_VOLTS);
+    }
+
+    /// Set the 12 bit DAC value directly.
+    pub fn set_raw(&mut self, output: CvOutput, value: u16) {
+        let value = Value::Bit12Right(value.min(FULL_SCALE as u16));
+        match output {
+            CvOutput::Out1 => self.out1.set(value),
+            CvOutput::Out2 => self.out2.set(value),
+        }
+    }
+}
+
+pub struct CvStream<'d> {
+    _out1: DacCh1<'d, DAC1, NoDma>,
+    _out2: DacCh2<'d, DAC1, NoDma>,
+    timer: Timer<'d, TIM6>,
+    /// Both outputs interleaved, output 1 in the low half of each word.
+    ring: WritableRingBuffer<'d, u32>,
+    /// Auto-reload value of TIM6 closest to the actual SAI rate.
+    period: u16,
+    running: bool,
+}
+
+impl CvStream<'_> {
+    /// Play blocks rendered by `callback`, which gets one block of values
+    /// from 0.0 to 1.0 for each output and runs while the previous blocks
+    /// are played. The stream is paced by TIM6 alone, see
+    /// [`CvStream::write`] to follow the audio instead.
+    pub async fn start(&mut self, mut callback: impl FnMut(&mut [f32], &mut [f32])) -> ! {
+        let mut block1 = [0.0; BLOCK_LENGTH];
+        let mut block2 = [0.0; BLOCK_LENGTH];
+        let mut frames = [0; BLOCK_LENGTH];
+        self.begin();
+        loop {
+            callback(&mut block1, &mut block2);
+            interleave(&block1, &block2, &mut frames);
+            if self.ring.write_exact(&frames).await.is_err() {
+                error!("Overrun on CV stream write");
+                self.ring.clear();
+            }
+        }
+    }
+
+    /// Queue one block of values from 0.0 to 1.0 for each output, without
+    /// waiting. Call this once per block from the audio callback: the
+    /// stream then plays two blocks behind the audio, and the period of TIM6
+    /// is adjusted by a tick every block to keep that distance. One tick is
+    /// twice the error left in the period, so the stream cannot drift. The
+    /// first call starts the stream.
+    pub fn write(&mut self, out1: &[f32], out2: &[f32]) -> Result<(), Error> {
+        let mut frames = [0; BLOCK_LENGTH];
+        let len = interleave(out1, out2, &mut frames);
+        if !self.running {
+            self.begin();
+        }
+        let free = match self.ring.write(&frames[..len]) {
+            Ok((written, free)) if written == len => free,
+            _ => {
+                self.ring.clear();
+                return Err(Error::Overrun);
+            }
+        };
+
+        // more queued than the target means the DAC is too slow
+        let queued = self.ring.capacity() - free;
+        let period = match queued.cmp(&(BLOCK_LENGTH * STREAM_BLOCKS / 2)) {
+            Ordering::Less => self.period + 1,
+            Ordering::Equal => self.period,
+            Ordering::Greater => self.period - 1,
+        };
+        self.timer.regs_basic().arr().write(|w| w.set_arr(period));
+        Ok(())
+    }
+
+    /// Queue silence for half of the buffer and start playing.
+    fn begin(&mut self) {
+        let silence = [0; BLOCK_LENGTH];
+        for _ in 0..STREAM_BLOCKS / 2 {
+            self.ring.write_immediate(&silence).ok();
+        }
+        self.ring.start();
+        self.timer.start();
+        self.running = true;
+    }
+}
+
+fn to_raw(value: f32) -> u16 {
+    (value.clamp(0.0, 1.0) * FULL_SCALE + 0.5) as u16
+}
+
+/// Pack the values of both outputs into the words of the dual channel
+/// register, returning the number of words.
+fn interleave(out1: &[f32], out2: &[f32], frames: &mut [u32]) -> usize {
+    let mut len = 0;
+    for ((frame, value1), value2) in frames.iter_mut().zip(out1).zip(out2) {
+        *frame = to_raw(*value1) as u32 | (to_raw(*value2) as u32) << 16;
+        len += 1;
+    }
+    len
+}
@@ -0,0 +1,246 @@
+//! Quadrature rotary encoders, optionally with a push button.
+//!
+//! Both encoder pins are watched with EXTI, so any two
+//! [`DaisyPins`](crate::pins::DaisyPins) with different EXTI lines work.
+//! Turning fast can be accelerated, so one sweep covers a large range while
+//! slow turns still move by single steps.
+//!
+//! ```ignore
+//! let button = Switch::new(board.pins.d1, p.EXTI11, Default::default());
+//! let mut encoder = Encoder::new(board.pins.d2, p.EXTI10, board.pins.d3, p.EXTI9, Default::default())
+//!     .with_switch(button);
+//! loop {
+//!     match encoder.wait_for_event().await {
+//!         EncoderEvent::Turned(steps) => value += steps,
+//!         EncoderEvent::Switch(Event::Pressed) => value = 0,
+//!         _ => {}
+//!     }
+//! }
+//! ```
+//!
+//! The decoding is done by [`EncoderState`], which works on any reading and
+//! time stamp.
+
+use crate::switch::{Event, Switch, IDLE_POLL};
+use embassy_futures::select::{select, select3, Either3};
+use embassy_stm32 as hal;
+use embassy_time::{Duration, Instant, Timer};
+use hal::exti::ExtiInput;
+use hal::gpio::{Pin, Pull};
+use hal::Peripheral;
+
+/// Quarter steps by previous and current `(a << 1) | b`. Transitions
+/// skipping a state are bounces or missed edges and count as nothing; the
+/// count starts over at each detent, so they cannot add up.
+const TRANSITIONS: [[i8; 4]; 4] = [[0, -1, 1, 0], [1, 0, 0, -1], [-1, 0, 0, 1], [0, 1, -1, 0]];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Acceleration {
+    /// Detents closer together than this are accelerated.
+    pub threshold: Duration,
+    /// Largest number of steps reported for one detent.
+    pub max_multiplier: u8,
+}
+
+impl Default for Acceleration {
+    fn default() -> Self {
+        Acceleration {
+            threshold: Duration::from_millis(40),
+            max_multiplier: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct EncoderConfig {
+    pub pull: Pull,
+    /// Quadrature steps between two detents, 4 for most panel encoders.
+    /// With 4 the encoder rests on one pin code, with 2 on that code and its
+    /// opposite, and with 1 on every code.
+    pub steps_per_detent: u8,
+    /// Count the other way round.
+    pub reverse: bool,
+    pub acceleration: Option<Acceleration>,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        EncoderConfig {
+            pull: Pull::Up,
+            steps_per_detent: 4,
+            reverse: false,
+            acceleration: None,
+        }
+    }
+}
+
+/// Quadrature decoding and acceleration, independent of the pins.
+pub struct EncoderState {
+    config: EncoderConfig,
+    state: u8,
+    /// Code of the pins at a detent.
+    rest: u8,
+    /// Quarter steps since the last detent.
+    steps: i8,
+    last_detent: Option<Instant>,
+}
+
+impl EncoderState {
+    pub const fn new(config: EncoderConfig) -> Self {
+        Self {
+            config,
+            state: 0b11,
+            rest: 0b11,
+            steps: 0,
+            last_detent: None,
+        }
+    }
+
+    pub fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    /// Start from the current pin levels, e.g. a detent resting with `a`
+    /// and `b` low.
+    pub fn reset(&mut self, a: bool, b: bool) {
+        self.state = (a as u8) << 1 | b as u8;
+        self.rest = self.state;
+        self.steps = 0;
+    }
+
+    /// Feed the pin levels and get the increments since the last update:
+    /// positive clockwise, negative counterclockwise.
+    ///
+    /// A detent is counted when the pins come to rest, if the encoder moved
+    /// more than half a detent since the last one.
+    pub fn update(&mut self, a: bool, b: bool, now: Instant) -> i32 {
+        let state = (a as u8) << 1 | b as u8;
+        self.steps += TRANSITIONS[self.state as usize][state as usize];
+        self.state = state;
+        if !self.is_at_rest() {
+            return 0;
+        }
+
+        let steps = core::mem::take(&mut self.steps);
+        let per_detent = self.config.steps_per_detent.clamp(1, 4) as i8;
+        if steps.abs() * 2 < per_detent {
+            return 0;
+        }
+        let direction = steps.signum() as i32;
+        let direction = if self.config.reverse {
+            -direction
+        } else {
+            direction
+        };
+        direction * self.multiplier(now)
+    }
+
+    fn is_at_rest(&self) -> bool {
+        match self.config.steps_per_detent {
+            0 | 1 => true,
+            2 | 3 => self.state == self.rest || self.state == self.rest ^ 0b11,
+            _ => self.state == self.rest,
+        }
+    }
+
+    fn multiplier(&mut self, now: Instant) -> i32 {
+        let last = self.last_detent.replace(now);
+        let (Some(acceleration), Some(last)) = (self.config.acceleration, last) else {
+            return 1;
+        };
+        let interval = (now - last).as_micros().max(1);
+        let multiplier = acceleration.threshold.as_micros() / interval;
+        multiplier.clamp(1, acceleration.max_multiplier.max(1) as u64) as i32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum EncoderEvent {
+    /// Steps turned, positive clockwise.
+    Turned(i32),
+    Switch(Event),
+}
+
+pub struct Encoder<'d> {
+    a: ExtiInput<'d>,
+    b: ExtiInput<'d>,
+    state: EncoderState,
+    switch: Option<Switch<'d>>,
+}
+
+impl<'d> Encoder<'d> {
+    pub fn new<A: Pin, B: Pin>(
+        a: impl Peripheral<P = A> + 'd,
+        a_channel: impl Peripheral<P = A::ExtiChannel> + 'd,
+        b: impl Peripheral<P = B> + 'd,
+        b_channel: impl Peripheral<P = B::ExtiChannel> + 'd,
+        config: EncoderConfig,
+    ) -> Self {
+        let a = ExtiInput::new(a, a_channel, config.pull);
+        let b = ExtiInput::new(b, b_channel, config.pull);
+        let mut state = EncoderState::new(config);
+        state.reset(a.is_high(), b.is_high());
+        Self {
+            a,
+            b,
+            state,
+            switch: None,
+        }
+    }
+
+    /// Add the push button of the encoder.
+    pub fn with_switch(mut self, switch: Switch<'d>) -> Self {
+        self.switch = Some(switch);
+        self
+    }
+
+    pub fn switch(&self) -> Option<&Switch<'d>> {
+        self.switch.as_ref()
+    }
+
+    pub fn switch_mut(&mut self) -> Option<&mut Switch<'d>> {
+        self.switch.as_mut()
+    }
+
+    /// Read the pins and get the steps turned since the last update, without
+    /// waiting. Polling has to be fast enough to see every quadrature step,
+    /// once per audio block is.
+    pub fn update(&mut self) -> i32 {
+        self.state
+            .update(self.a.is_high(), self.b.is_high(), Instant::now())
+    }
+
+    /// Wait until the encoder is turned by at least one detent.
+    pub async fn wait_for_turn(&mut self) -> i32 {
+        loop {
+            let steps = self.update();
+            if steps != 0 {
+                return steps;
+            }
+            let edge = select(self.a.wait_for_any_edge(), self.b.wait_for_any_edge());
+            select(edge, Timer::after(IDLE_POLL)).await;
+        }
+    }
+
+    /// Wait until the encoder is turned or its button does something.
+    /// Without a button, this is [`Encoder::wait_for_turn`].
+    pub async fn wait_for_event(&mut self) -> EncoderEvent {
+        let Some(switch) = self.switch.as_mut() else {
+            return EncoderEvent::Turned(self.wait_for_turn().await);
+        };
+        loop {
+            let steps = self
+                .state
+                .update(self.a.is_high(), self.b.is_high(), Instant::now());
+            if steps != 0 {
+                return EncoderEvent::Turned(steps);
+            }
+            let edge = select(self.a.wait_for_any_edge(), self.b.wait_for_any_edge());
+            if let Either3::Second(event) =
+                select3(edge, switch.wait_for_event(), Timer::after(IDLE_POLL)).await
+            {
+                return EncoderEvent::Switch(event);
+            }
+        }
+    }
+}
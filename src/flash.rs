@@ -1,6 +1,8 @@
 use crate::hal;
 use crate::pins::FlashPins;
+use core::ops::Range;
 use embassy_stm32::qspi::enums::{AddressSize, ChipSelectHighTime, FIFOThresholdLevel, MemorySize};
+use embassy_time::{block_for, Duration, Timer};
 use hal::{
     mode::Blocking,
     peripherals::QUADSPI,
@@ -16,13 +18,341 @@ const WRITE_CMD: u8 = 0x02; // PP
 const READ_STATUS_REGISTRY_CMD: u8 = 0x05; // RDSR
 const WRITE_ENABLE_CMD: u8 = 0x06; // WREN
 const ENTER_QPI_MODE_CMD: u8 = 0x35; // QPIEN
+const WRITE_FUNCTION_REGISTER_CMD: u8 = 0x42; // WRFR
+const READ_FUNCTION_REGISTER_CMD: u8 = 0x48; // RDFR
+const BLOCK_ERASE_32K_CMD: u8 = 0x52; // BER32
+const READ_READ_PARAMETERS_CMD: u8 = 0x61; // RDRP
+const GANG_BLOCK_LOCK_CMD: u8 = 0x7E; // GBLK
+const GANG_BLOCK_UNLOCK_CMD: u8 = 0x98; // GBUN
+const RELEASE_POWER_DOWN_CMD: u8 = 0xAB; // RDPD
+const READ_JEDEC_ID_QPI_CMD: u8 = 0xAF; // RDJDIDQ
+const DEEP_POWER_DOWN_CMD: u8 = 0xB9; // DP
 const SET_READ_PARAMETERS_CMD: u8 = 0xC0; // SRP
+const CHIP_ERASE_CMD: u8 = 0xC7; // CER
 const SECTOR_ERASE_CMD: u8 = 0xD7; // SER
+const BLOCK_ERASE_64K_CMD: u8 = 0xD8; // BER64
 const FAST_READ_QUAD_IO_CMD: u8 = 0xEB; // FRQIO
+const READ_DYB_CMD: u8 = 0xFA; // RDDYB
+const WRITE_DYB_CMD: u8 = 0xFB; // WRDYB
 
 // Memory array specifications as defined in the datasheet.
-const SECTOR_SIZE: u32 = 4096;
-const PAGE_SIZE: u32 = 256;
+pub const FLASH_SIZE: u32 = 8 * 1024 * 1024;
+pub const SECTOR_SIZE: u32 = 4096;
+pub const BLOCK_32K_SIZE: u32 = 32 * 1024;
+pub const BLOCK_64K_SIZE: u32 = 64 * 1024;
+pub const PAGE_SIZE: u32 = 256;
+
+// Timing specifications as defined in the datasheet.
+const DEEP_POWER_DOWN_TIME: Duration = Duration::from_micros(3); // tDP
+const RELEASE_POWER_DOWN_TIME: Duration = Duration::from_micros(5); // tRES1
+
+/// Interval between status polls while async code waits for the flash.
+const BUSY_POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+// Status register bits.
+const STATUS_WIP: u8 = 1 << 0;
+const STATUS_BP_MASK: u8 = 0b0011_1100;
+const STATUS_BP_SHIFT: u8 = 2;
+
+// Function register bits.
+const FUNCTION_TBS: u8 = 1 << 1;
+
+// Dynamic protection bit values.
+const DYB_PROTECTED: u8 = 0x00;
+const DYB_UNPROTECTED: u8 = 0xFF;
+
+/// JEDEC identification of the ISSI IS25LP064 mounted on the Daisy Seed.
+pub const IS25LP064_JEDEC_ID: JedecId = JedecId {
+    manufacturer: 0x9D,
+    memory_type: 0x60,
+    capacity: 0x17,
+};
+
+/// Manufacturer and device identification as returned by `RDJDID`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+/// Erase granularities supported by the IS25LP064.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum EraseKind {
+    Sector,
+    Block32K,
+    Block64K,
+    Chip,
+}
+
+impl EraseKind {
+    pub const fn size(self) -> u32 {
+        match self {
+            Self::Sector => SECTOR_SIZE,
+            Self::Block32K => BLOCK_32K_SIZE,
+            Self::Block64K => BLOCK_64K_SIZE,
+            Self::Chip => FLASH_SIZE,
+        }
+    }
+
+    const fn command(self) -> u8 {
+        match self {
+            Self::Sector => SECTOR_ERASE_CMD,
+            Self::Block32K => BLOCK_ERASE_32K_CMD,
+            Self::Block64K => BLOCK_ERASE_64K_CMD,
+            Self::Chip => CHIP_ERASE_CMD,
+        }
+    }
+}
+
+/// Iterator over the erase operations needed to clear a range of the flash.
+///
+/// The range is widened to sector boundaries, and every step uses the largest
+/// erase granularity that is aligned and fits in what is left. Use
+/// [`plan_erase`] to create one.
+#[derive(Debug, Clone)]
+pub struct ErasePlan {
+    address: u32,
+    end: u32,
+}
+
+/// Plan the erase of `length` bytes starting at `address`.
+pub fn plan_erase(address: u32, length: u32) -> ErasePlan {
+    assert!(length > 0);
+    assert!(address <= MAX_ADDRESS && length <= FLASH_SIZE - address);
+    let start = address & !(SECTOR_SIZE - 1);
+    let end = (address + length).next_multiple_of(SECTOR_SIZE);
+    ErasePlan {
+        address: start,
+        end,
+    }
+}
+
+impl Iterator for ErasePlan {
+    type Item = (EraseKind, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.address >= self.end {
+            return None;
+        }
+        let remaining = self.end - self.address;
+        let kind = [
+            EraseKind::Chip,
+            EraseKind::Block64K,
+            EraseKind::Block32K,
+            EraseKind::Sector,
+        ]
+        .into_iter()
+        .find(|kind| self.address & (kind.size() - 1) == 0 && kind.size() <= remaining)
+        .unwrap_or(EraseKind::Sector);
+        let address = self.address;
+        self.address += kind.size();
+        Some((kind, address))
+    }
+}
+
+/// Area locked by the non-volatile block-protect bits (BP0-BP3) of the status
+/// register, counted from the top of the array or from the bottom when the
+/// TBS bit of the function register is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct BlockProtection {
+    /// Value of BP3..BP0. Level `n` in `1..=7` protects `2^(n-1)` 64 KiB
+    /// blocks, levels `8..=15` protect the whole array.
+    pub level: u8,
+    /// Whether the protected area starts at the bottom of the array (TBS = 1).
+    pub bottom: bool,
+}
+
+impl BlockProtection {
+    pub const MAX_LEVEL: u8 = 15;
+
+    /// Address range made read-only by this setting.
+    pub fn protected_range(&self) -> Range<u32> {
+        assert!(self.level <= Self::MAX_LEVEL);
+        let size = match self.level {
+            0 => 0,
+            level @ 1..=7 => BLOCK_64K_SIZE << (level - 1),
+            _ => FLASH_SIZE,
+        };
+        if self.bottom {
+            0..size
+        } else {
+            FLASH_SIZE - size..FLASH_SIZE
+        }
+    }
+
+    /// Smallest protection level covering `range`. Ranges on the opposite
+    /// side of the array from `bottom` can only be covered by protecting it
+    /// entirely.
+    pub fn covering(range: Range<u32>, bottom: bool) -> Self {
+        assert!(range.end <= FLASH_SIZE);
+        (0..=8)
+            .map(|level| Self { level, bottom })
+            .find(|protection| {
+                let protected = protection.protected_range();
+                range.is_empty() || (protected.start <= range.start && range.end <= protected.end)
+            })
+            .unwrap()
+    }
+
+    pub fn is_protected(&self, address: u32) -> bool {
+        self.protected_range().contains(&address)
+    }
+}
+
+/// NOR flash operations the storage layers of this crate are built on.
+///
+/// Implemented by [`Flash`] for the on-board QSPI chip and by [`MemFlash`],
+/// which models the same behaviour in RAM.
+pub trait Storage {
+    /// Size of the memory in bytes.
+    fn capacity(&self) -> u32;
+    fn read(&mut self, address: u32, buffer: &mut [u8]);
+    /// Start programming `data`, which must not cross a [`PAGE_SIZE`]
+    /// boundary, without waiting for completion.
+    fn begin_program_page(&mut self, address: u32, data: &[u8]);
+    /// Start erasing the sector containing `address` without waiting for
+    /// completion.
+    fn begin_erase_sector(&mut self, address: u32);
+    /// Whether a program or erase operation is still running.
+    fn is_busy(&mut self) -> bool;
+
+    /// Write `data` without erasing. Programming can only turn bits from 1
+    /// to 0.
+    fn program(&mut self, address: u32, data: &[u8]) {
+        for (address, page) in pages(address, data) {
+            self.begin_program_page(address, page);
+            while self.is_busy() {}
+        }
+    }
+
+    /// Set the [`SECTOR_SIZE`] bytes of the sector containing `address` to
+    /// `0xFF`.
+    fn erase_sector(&mut self, address: u32) {
+        self.begin_erase_sector(address);
+        while self.is_busy() {}
+    }
+}
+
+/// Lets several storage layers share one chip, e.g. a [`Settings`] store
+/// and a [`PresetBank`] in different ranges of the [`Flash`].
+///
+/// [`Settings`]: crate::settings::Settings
+/// [`PresetBank`]: crate::preset::PresetBank
+impl<S: Storage + ?Sized> Storage for &mut S {
+    fn capacity(&self) -> u32 {
+        (**self).capacity()
+    }
+    fn read(&mut self, address: u32, buffer: &mut [u8]) {
+        (**self).read(address, buffer)
+    }
+    fn begin_program_page(&mut self, address: u32, data: &[u8]) {
+        (**self).begin_program_page(address, data)
+    }
+    fn begin_erase_sector(&mut self, address: u32) {
+        (**self).begin_erase_sector(address)
+    }
+    fn is_busy(&mut self) -> bool {
+        (**self).is_busy()
+    }
+    fn program(&mut self, address: u32, data: &[u8]) {
+        (**self).program(address, data)
+    }
+    fn erase_sector(&mut self, address: u32) {
+        (**self).erase_sector(address)
+    }
+}
+
+/// Like [`Storage::program`], but yields to other tasks while the flash is
+/// busy, so long writes do not stall the executor.
+pub async fn program_async<S: Storage>(storage: &mut S, address: u32, data: &[u8]) {
+    for (address, page) in pages(address, data) {
+        storage.begin_program_page(address, page);
+        wait_ready(storage).await;
+    }
+}
+
+/// Like [`Storage::erase_sector`], but yields to other tasks while the flash
+/// is busy.
+pub async fn erase_sector_async<S: Storage>(storage: &mut S, address: u32) {
+    storage.begin_erase_sector(address);
+    wait_ready(storage).await;
+}
+
+async fn wait_ready<S: Storage>(storage: &mut S) {
+    while storage.is_busy() {
+        Timer::after(BUSY_POLL_INTERVAL).await;
+    }
+}
+
+/// Split `data` to be written at `address` into chunks that do not cross a
+/// page boundary.
+fn pages(mut address: u32, mut data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    core::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+        // Calculate number of bytes between address and end of the page.
+        let page_remainder = (PAGE_SIZE - (address & (PAGE_SIZE - 1))) as usize;
+        let (page, rest) = data.split_at(page_remainder.min(data.len()));
+        let chunk = (address, page);
+        address += page.len() as u32;
+        data = rest;
+        Some(chunk)
+    })
+}
+
+/// In-memory flash model with the same program/erase semantics as the
+/// IS25LP064, for testing code written against [`Storage`].
+pub struct MemFlash<const SIZE: usize> {
+    data: [u8; SIZE],
+}
+
+impl<const SIZE: usize> MemFlash<SIZE> {
+    /// Create a fully erased memory. `SIZE` must be a multiple of
+    /// [`SECTOR_SIZE`].
+    pub const fn new() -> Self {
+        assert!(SIZE.is_multiple_of(SECTOR_SIZE as usize));
+        Self { data: [0xFF; SIZE] }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl<const SIZE: usize> Default for MemFlash<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> Storage for MemFlash<SIZE> {
+    fn capacity(&self) -> u32 {
+        SIZE as u32
+    }
+
+    fn read(&mut self, address: u32, buffer: &mut [u8]) {
+        let start = address as usize;
+        buffer.copy_from_slice(&self.data[start..start + buffer.len()]);
+    }
+
+    fn begin_program_page(&mut self, address: u32, data: &[u8]) {
+        let start = address as usize;
+        for (cell, byte) in self.data[start..start + data.len()].iter_mut().zip(data) {
+            *cell &= byte;
+        }
+    }
+
+    fn begin_erase_sector(&mut self, address: u32) {
+        let start = (address & !(SECTOR_SIZE - 1)) as usize;
+        self.data[start..start + SECTOR_SIZE as usize].fill(0xFF);
+    }
+
+    fn is_busy(&mut self) -> bool {
+        false
+    }
+}
 
 pub struct FlashBuilder {
     pub pins: FlashPins,
@@ -42,7 +372,13 @@ impl FlashBuilder {
         let qspi = Qspi::new_blocking_bank1(
             qspi, pins.IO0, pins.IO1, pins.IO2, pins.IO3, pins.SCK, pins.CS, config,
         );
-        let mut result = Flash { qspi };
+        let mut result = Flash {
+            qspi,
+            powered_down: false,
+        };
+        // after a warm reset the device may still be in deep power-down,
+        // where it ignores everything but the release command
+        result.force_release_power_down();
         result.enable_qpi_mode();
         result.reset_status_register();
         result.reset_read_register();
@@ -53,11 +389,13 @@ const MAX_ADDRESS: u32 = 0x7FFFFF;
 
 pub struct Flash<'a> {
     qspi: Qspi<'a, QUADSPI, Blocking>,
+    powered_down: bool,
 }
 
 impl Flash<'_> {
     pub fn read(&mut self, address: u32, buffer: &mut [u8]) {
         assert!(address <= MAX_ADDRESS);
+        self.ensure_awake();
 
         let transaction = TransferConfig {
             iwidth: QspiWidth::QUAD,
@@ -71,6 +409,7 @@ impl Flash<'_> {
     }
 
     pub fn read_uuid(&mut self) -> [u8; 16] {
+        self.ensure_awake();
         let mut buffer = [0; 16];
         let transaction: TransferConfig = TransferConfig {
             iwidth: QspiWidth::QUAD,
@@ -84,126 +423,373 @@ impl Flash<'_> {
         buffer
     }
 
-    pub fn write(&mut self, mut address: u32, data: &[u8]) {
+    /// Erase the sectors covered by `data` and write it at `address`.
+    ///
+    /// Everything else stored in those sectors is lost. Use
+    /// [`Flash::program`] to write into an already erased area instead.
+    pub fn write(&mut self, address: u32, data: &[u8]) {
         assert!(address <= MAX_ADDRESS);
         assert!(!data.is_empty());
         self.erase(address, data.len() as u32);
+        self.program(address, data);
+    }
 
-        let mut length = data.len() as u32;
-        let mut start_cursor = 0;
+    /// Write `data` at `address` without erasing first. Programming can only
+    /// clear bits, so the target area is expected to be erased.
+    pub fn program(&mut self, address: u32, data: &[u8]) {
+        assert!(address <= MAX_ADDRESS);
 
         //WRITE_CMD(or PP) allows to write up to 256 bytes, which is as much as PAGE_SIZE.
         //Let's divide the data into chunks of page size to write to flash
-        loop {
-            // Calculate number of bytes between address and end of the page.
-            let page_remainder = PAGE_SIZE - (address & (PAGE_SIZE - 1));
-            let size = page_remainder.min(length) as usize;
-            self.enable_write();
-            let transaction = TransferConfig {
-                iwidth: QspiWidth::QUAD,
-                awidth: QspiWidth::QUAD,
-                dwidth: QspiWidth::QUAD,
-                instruction: WRITE_CMD,
-                address: Some(address),
-                dummy: DummyCycles::_0,
-            };
-
-            self.qspi
-                .blocking_write(&data[start_cursor..start_cursor + size], transaction);
+        for (address, page) in pages(address, data) {
+            self.begin_program_page(address, page);
             self.wait_for_write();
-            start_cursor += size;
+        }
+    }
 
-            // Stop if this was the last needed page.
-            if length <= page_remainder {
-                break;
-            }
-            length -= page_remainder;
+    fn begin_program_page(&mut self, address: u32, data: &[u8]) {
+        self.enable_write();
+        let transaction = TransferConfig {
+            iwidth: QspiWidth::QUAD,
+            awidth: QspiWidth::QUAD,
+            dwidth: QspiWidth::QUAD,
+            instruction: WRITE_CMD,
+            address: Some(address),
+            dummy: DummyCycles::_0,
+        };
+        self.qspi.blocking_write(data, transaction);
+    }
 
-            // Jump to the next page.
-            address += page_remainder;
-            address %= MAX_ADDRESS;
+    /// Erase every sector touched by `length` bytes starting at `address`,
+    /// using block and chip erase wherever the range allows it.
+    pub fn erase(&mut self, address: u32, length: u32) {
+        for (kind, address) in plan_erase(address, length) {
+            self.erase_unit(kind, address);
         }
     }
 
-    pub fn erase(&mut self, mut address: u32, mut length: u32) {
+    /// Erase the 4 KiB sector containing `address`.
+    pub fn erase_sector(&mut self, address: u32) {
+        self.erase_unit(EraseKind::Sector, address & !(SECTOR_SIZE - 1));
+    }
+
+    /// Erase the 32 KiB block containing `address`.
+    pub fn erase_block_32k(&mut self, address: u32) {
+        self.erase_unit(EraseKind::Block32K, address & !(BLOCK_32K_SIZE - 1));
+    }
+
+    /// Erase the 64 KiB block containing `address`.
+    pub fn erase_block_64k(&mut self, address: u32) {
+        self.erase_unit(EraseKind::Block64K, address & !(BLOCK_64K_SIZE - 1));
+    }
+
+    /// Erase the whole memory array.
+    pub fn erase_chip(&mut self) {
+        self.erase_unit(EraseKind::Chip, 0);
+    }
+
+    fn erase_unit(&mut self, kind: EraseKind, address: u32) {
+        self.begin_erase(kind, address);
+        self.wait_for_write();
+    }
+
+    fn begin_erase(&mut self, kind: EraseKind, address: u32) {
         assert!(address <= MAX_ADDRESS);
-        assert!(length > 0);
 
-        loop {
-            // Erase the sector.
-            self.enable_write();
-            let transaction = TransferConfig {
-                iwidth: QspiWidth::QUAD,
-                awidth: QspiWidth::QUAD,
-                dwidth: QspiWidth::NONE,
-                instruction: SECTOR_ERASE_CMD,
-                address: Some(address),
-                dummy: DummyCycles::_0,
-            };
+        self.enable_write();
+        let transaction = TransferConfig {
+            iwidth: QspiWidth::QUAD,
+            awidth: match kind {
+                EraseKind::Chip => QspiWidth::NONE,
+                _ => QspiWidth::QUAD,
+            },
+            dwidth: QspiWidth::NONE,
+            instruction: kind.command(),
+            address: match kind {
+                EraseKind::Chip => None,
+                _ => Some(address),
+            },
+            dummy: DummyCycles::_0,
+        };
+        self.qspi.blocking_command(transaction);
+    }
 
-            self.qspi.blocking_command(transaction);
-            self.wait_for_write();
+    /// Read the JEDEC manufacturer and device ID. Compare it against
+    /// [`IS25LP064_JEDEC_ID`] to verify the part.
+    pub fn read_jedec_id(&mut self) -> JedecId {
+        let mut buffer = [0; 3];
+        self.read_register(READ_JEDEC_ID_QPI_CMD, &mut buffer);
+        JedecId {
+            manufacturer: buffer[0],
+            memory_type: buffer[1],
+            capacity: buffer[2],
+        }
+    }
 
-            // Calculate number of bytes between address and end of the sector.
-            let sector_remainder = SECTOR_SIZE - (address & (SECTOR_SIZE - 1));
+    /// Read the status register (WIP, WEL, BP0-BP3, QE and SRWD bits).
+    pub fn read_status_register(&mut self) -> u8 {
+        let mut status = [0xFF; 1];
+        self.read_register(READ_STATUS_REGISTRY_CMD, &mut status);
+        status[0]
+    }
 
-            // Stop if this was the last affected sector.
-            if length <= sector_remainder {
-                break;
-            }
-            length -= sector_remainder;
+    /// Read the function register (TBS, PSUS, ESUS and IRL bits).
+    pub fn read_function_register(&mut self) -> u8 {
+        let mut function = [0; 1];
+        self.read_register(READ_FUNCTION_REGISTER_CMD, &mut function);
+        function[0]
+    }
 
-            // Jump to the next sector.
-            address += sector_remainder;
-            address %= MAX_ADDRESS;
+    /// Read the read parameters register (burst length, dummy cycles and
+    /// output driver strength).
+    pub fn read_read_parameters(&mut self) -> u8 {
+        let mut parameters = [0; 1];
+        self.read_register(READ_READ_PARAMETERS_CMD, &mut parameters);
+        parameters[0]
+    }
+
+    /// Read the current block-protect setting.
+    pub fn block_protection(&mut self) -> BlockProtection {
+        let status = self.read_status_register();
+        BlockProtection {
+            level: (status & STATUS_BP_MASK) >> STATUS_BP_SHIFT,
+            bottom: self.read_function_register() & FUNCTION_TBS != 0,
         }
     }
 
-    fn enable_write(&mut self) {
+    /// Make `range` read-only by raising the block-protect bits just enough
+    /// to cover it. Protection already in place is never lowered, that is
+    /// left to [`Flash::unprotect`]. The setting is non-volatile and
+    /// survives power cycles. Returns the applied protection, which may
+    /// cover more than `range`.
+    pub fn protect(&mut self, range: Range<u32>) -> BlockProtection {
+        let current = self.block_protection();
+        let covering = BlockProtection::covering(range, current.bottom);
+        let protection = BlockProtection {
+            level: covering.level.max(current.level),
+            bottom: current.bottom,
+        };
+        if protection.level != current.level {
+            self.set_block_protection_level(protection.level);
+        }
+        protection
+    }
+
+    /// Clear the block-protect bits so the whole array is writable again.
+    pub fn unprotect(&mut self) {
+        self.set_block_protection_level(0);
+    }
+
+    /// Write BP3..BP0 of the status register.
+    pub fn set_block_protection_level(&mut self, level: u8) {
+        assert!(level <= BlockProtection::MAX_LEVEL);
+        let status = self.read_status_register() & !STATUS_BP_MASK;
+        self.write_status_register(status | (level << STATUS_BP_SHIFT));
+    }
+
+    /// Make block protection count from the bottom of the array.
+    ///
+    /// The TBS bit is one-time programmable: once set it can never be
+    /// cleared again on this chip.
+    pub fn set_bottom_protection_permanently(&mut self) {
+        let function = self.read_function_register();
+        if function & FUNCTION_TBS == 0 {
+            self.write_function_register(function | FUNCTION_TBS);
+        }
+    }
+
+    /// Lock the 64 KiB block containing `address` with its dynamic
+    /// protection bit. Dynamic protection is volatile: it is cleared by a
+    /// power cycle and works alongside the block-protect bits.
+    pub fn lock_block(&mut self, address: u32) {
+        self.write_dyb(address, DYB_PROTECTED);
+    }
+
+    /// Unlock the 64 KiB block containing `address`.
+    pub fn unlock_block(&mut self, address: u32) {
+        self.write_dyb(address, DYB_UNPROTECTED);
+    }
+
+    /// Whether the block containing `address` is locked by its dynamic
+    /// protection bit.
+    pub fn is_block_locked(&mut self, address: u32) -> bool {
+        assert!(address <= MAX_ADDRESS);
+        self.ensure_awake();
+        let mut dyb = [DYB_UNPROTECTED; 1];
+        let transaction = TransferConfig {
+            iwidth: QspiWidth::QUAD,
+            awidth: QspiWidth::QUAD,
+            dwidth: QspiWidth::QUAD,
+            instruction: READ_DYB_CMD,
+            address: Some(address & !(BLOCK_64K_SIZE - 1)),
+            dummy: DummyCycles::_0,
+        };
+        self.qspi.blocking_read(&mut dyb, transaction);
+        dyb[0] == DYB_PROTECTED
+    }
+
+    /// Lock every block with the dynamic protection bits.
+    pub fn lock_all_blocks(&mut self) {
+        self.gang_command(GANG_BLOCK_LOCK_CMD);
+    }
+
+    /// Unlock every block locked with the dynamic protection bits.
+    pub fn unlock_all_blocks(&mut self) {
+        self.gang_command(GANG_BLOCK_UNLOCK_CMD);
+    }
+
+    fn write_dyb(&mut self, address: u32, value: u8) {
+        assert!(address <= MAX_ADDRESS);
+        self.enable_write();
+        let transaction = TransferConfig {
+            iwidth: QspiWidth::QUAD,
+            awidth: QspiWidth::QUAD,
+            dwidth: QspiWidth::QUAD,
+            instruction: WRITE_DYB_CMD,
+            address: Some(address & !(BLOCK_64K_SIZE - 1)),
+            dummy: DummyCycles::_0,
+        };
+        self.qspi.blocking_write(&[value], transaction);
+        self.wait_for_write();
+    }
+
+    fn gang_command(&mut self, instruction: u8) {
+        self.enable_write();
         let transaction = TransferConfig {
             iwidth: QspiWidth::QUAD,
             awidth: QspiWidth::NONE,
             dwidth: QspiWidth::NONE,
-            instruction: WRITE_ENABLE_CMD,
+            instruction,
             address: None,
             dummy: DummyCycles::_0,
         };
         self.qspi.blocking_command(transaction);
+        self.wait_for_write();
     }
 
-    fn wait_for_write(&mut self) {
-        loop {
-            let mut status: [u8; 1] = [0xFF; 1];
+    fn write_status_register(&mut self, value: u8) {
+        self.write_register(WRITE_STATUS_REGISTRY_CMD, value);
+    }
+
+    fn write_function_register(&mut self, value: u8) {
+        self.write_register(WRITE_FUNCTION_REGISTER_CMD, value);
+    }
+
+    fn write_register(&mut self, instruction: u8, value: u8) {
+        self.enable_write();
+        let transaction = TransferConfig {
+            iwidth: QspiWidth::QUAD,
+            awidth: QspiWidth::NONE,
+            dwidth: QspiWidth::QUAD,
+            instruction,
+            address: None,
+            dummy: DummyCycles::_0,
+        };
+        self.qspi.blocking_write(&[value], transaction);
+        self.wait_for_write();
+    }
+
+    fn read_register(&mut self, instruction: u8, buffer: &mut [u8]) {
+        self.ensure_awake();
+        let transaction = TransferConfig {
+            iwidth: QspiWidth::QUAD,
+            awidth: QspiWidth::NONE,
+            dwidth: QspiWidth::QUAD,
+            instruction,
+            address: None,
+            dummy: DummyCycles::_0,
+        };
+        self.qspi.blocking_read(buffer, transaction);
+    }
+
+    /// Put the device into deep power-down. Any later operation on the
+    /// flash releases it automatically, or call
+    /// [`Flash::release_power_down`] to wake it explicitly.
+    pub fn power_down(&mut self) {
+        if self.powered_down {
+            return;
+        }
+        let transaction = TransferConfig {
+            iwidth: QspiWidth::QUAD,
+            awidth: QspiWidth::NONE,
+            dwidth: QspiWidth::NONE,
+            instruction: DEEP_POWER_DOWN_CMD,
+            address: None,
+            dummy: DummyCycles::_0,
+        };
+        self.qspi.blocking_command(transaction);
+        block_for(DEEP_POWER_DOWN_TIME);
+        self.powered_down = true;
+    }
+
+    /// Bring the device back from deep power-down.
+    pub fn release_power_down(&mut self) {
+        if !self.powered_down {
+            return;
+        }
+        let transaction = TransferConfig {
+            iwidth: QspiWidth::QUAD,
+            awidth: QspiWidth::NONE,
+            dwidth: QspiWidth::NONE,
+            instruction: RELEASE_POWER_DOWN_CMD,
+            address: None,
+            dummy: DummyCycles::_0,
+        };
+        self.qspi.blocking_command(transaction);
+        block_for(RELEASE_POWER_DOWN_TIME);
+        self.powered_down = false;
+    }
+
+    /// Send the release command whatever state the device is in. It may be
+    /// in QPI or SPI mode, so the command goes out in both widths.
+    fn force_release_power_down(&mut self) {
+        for iwidth in [QspiWidth::QUAD, QspiWidth::SING] {
             let transaction = TransferConfig {
-                iwidth: QspiWidth::QUAD,
+                iwidth,
                 awidth: QspiWidth::NONE,
-                dwidth: QspiWidth::QUAD,
-                instruction: READ_STATUS_REGISTRY_CMD,
+                dwidth: QspiWidth::NONE,
+                instruction: RELEASE_POWER_DOWN_CMD,
                 address: None,
                 dummy: DummyCycles::_0,
             };
-            self.qspi.blocking_read(&mut status, transaction);
-
-            if status[0] & 0x01 == 0 {
-                break;
-            }
+            self.qspi.blocking_command(transaction);
         }
+        block_for(RELEASE_POWER_DOWN_TIME);
+        self.powered_down = false;
     }
 
-    /// Reset status registers into driver's defaults. This makes sure that the
-    /// peripheral is configured as expected.
-    fn reset_status_register(&mut self) {
-        self.enable_write();
+    pub fn is_powered_down(&self) -> bool {
+        self.powered_down
+    }
+
+    /// The device ignores every command but release while powered down.
+    fn ensure_awake(&mut self) {
+        self.release_power_down();
+    }
+
+    fn enable_write(&mut self) {
+        self.ensure_awake();
         let transaction = TransferConfig {
             iwidth: QspiWidth::QUAD,
-            awidth: QspiWidth::QUAD,
+            awidth: QspiWidth::NONE,
             dwidth: QspiWidth::NONE,
-            instruction: WRITE_STATUS_REGISTRY_CMD,
-            address: Some(0b0000_0010),
+            instruction: WRITE_ENABLE_CMD,
+            address: None,
             dummy: DummyCycles::_0,
         };
         self.qspi.blocking_command(transaction);
-        self.wait_for_write();
+    }
+
+    fn wait_for_write(&mut self) {
+        while self.read_status_register() & STATUS_WIP != 0 {}
+    }
+
+    /// Reset status registers into driver's defaults. This makes sure that the
+    /// peripheral is configured as expected. Block-protect bits are kept, so
+    /// protected regions stay locked across resets.
+    fn reset_status_register(&mut self) {
+        let status = self.read_status_register();
+        self.write_status_register(status & STATUS_BP_MASK);
     }
 
     /// Reset read registers into driver's defaults. This makes sure that the
@@ -236,3 +822,33 @@ impl Flash<'_> {
         self.wait_for_write();
     }
 }
+
+impl Storage for Flash<'_> {
+    fn capacity(&self) -> u32 {
+        FLASH_SIZE
+    }
+
+    fn read(&mut self, address: u32, buffer: &mut [u8]) {
+        Flash::read(self, address, buffer);
+    }
+
+    fn begin_program_page(&mut self, address: u32, data: &[u8]) {
+        Flash::begin_program_page(self, address, data);
+    }
+
+    fn begin_erase_sector(&mut self, address: u32) {
+        self.begin_erase(EraseKind::Sector, address & !(SECTOR_SIZE - 1));
+    }
+
+    fn is_busy(&mut self) -> bool {
+        self.read_status_register() & STATUS_WIP != 0
+    }
+
+    fn program(&mut self, address: u32, data: &[u8]) {
+        Flash::program(self, address, data);
+    }
+
+    fn erase_sector(&mut self, address: u32) {
+        Flash::erase_sector(self, address);
+    }
+}
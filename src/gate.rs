@@ -0,0 +1,194 @@
+//! Gate and trigger outputs on any of the
+//! [`DaisyPins`](crate::pins::DaisyPins).
+//!
+//! A [`GateOut`] is a handle shared through a `static`: [`GateOut::set`] and
+//! the trigger methods only post a command and never block, so they can be
+//! called from tasks and from the audio callback alike. The pin itself is
+//! driven by [`GateOut::run`] in its own task, which times the pulses.
+//!
+//! ```ignore
+//! static CLOCK: GateOut = GateOut::new();
+//!
+//! #[embassy_executor::task]
+//! async fn clock_out(pin: GatePin<'static>) {
+//!     CLOCK.run(pin).await;
+//! }
+//!
+//! spawner.spawn(clock_out(GatePin::new(board.pins.d7, false)))?;
+//! // in the audio callback, a clock tick on sample 12 of this block
+//! CLOCK.trigger_at_sample(12, Fs::Fs48000, Duration::from_millis(5));
+//! ```
+
+use crate::audio::{Fs, BLOCK_LENGTH};
+use embassy_futures::select::{select, Either};
+use embassy_stm32 as hal;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use hal::gpio::{Level, Output, Pin, Speed};
+use hal::Peripheral;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Command {
+    Set(bool),
+    /// A pulse of `width`, starting at `at`.
+    Trigger {
+        at: Instant,
+        width: Duration,
+    },
+}
+
+/// Timing of a gate output, independent of the pin.
+pub struct GateSchedule {
+    high: bool,
+    /// A pulse still to start.
+    pending: Option<(Instant, Duration)>,
+    /// End of the running pulse.
+    off_at: Option<Instant>,
+    /// The output was cut low for the pending pulse, which has to wait a
+    /// tick so it starts with a new edge.
+    cut: bool,
+}
+
+impl GateSchedule {
+    pub const fn new() -> Self {
+        Self {
+            high: false,
+            pending: None,
+            off_at: None,
+            cut: false,
+        }
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+
+    /// Take a command. A new command replaces anything still scheduled.
+    /// A pulse triggered while the output is high starts after the output
+    /// went low for at least a tick, instead of merging with the high.
+    pub fn command(&mut self, command: Command) {
+        self.pending = None;
+        self.off_at = None;
+        self.cut = false;
+        match command {
+            Command::Set(high) => self.high = high,
+            Command::Trigger { at, width } => {
+                self.pending = Some((at, width));
+                self.cut = self.high;
+                self.high = false;
+            }
+        }
+    }
+
+    /// Advance to `now` and return the level of the output.
+    pub fn update(&mut self, now: Instant) -> bool {
+        if let (Some((at, width)), true) = (self.pending, core::mem::take(&mut self.cut)) {
+            self.pending = Some((at.max(now + Duration::from_ticks(1)), width));
+        }
+        if let Some((at, width)) = self.pending {
+            if at <= now {
+                self.pending = None;
+                self.high = true;
+                self.off_at = Some(at + width);
+            }
+        }
+        if let Some(off_at) = self.off_at {
+            if off_at <= now {
+                self.off_at = None;
+                self.high = false;
+            }
+        }
+        self.high
+    }
+
+    /// When the level changes next, without further commands.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.map(|(at, _)| at).or(self.off_at)
+    }
+}
+
+impl Default for GateSchedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The output pin of a [`GateOut`].
+pub struct GatePin<'d> {
+    output: Output<'d>,
+    inverted: bool,
+}
+
+impl<'d> GatePin<'d> {
+    /// `inverted` for output stages that invert the signal, such as a
+    /// single transistor.
+    pub fn new(pin: impl Peripheral<P = impl Pin> + 'd, inverted: bool) -> Self {
+        let level = if inverted { Level::High } else { Level::Low };
+        Self {
+            output: Output::new(pin, level, Speed::Low),
+            inverted,
+        }
+    }
+
+    pub fn set(&mut self, high: bool) {
+        self.output.set_level((high != self.inverted).into());
+    }
+}
+
+pub struct GateOut {
+    commands: Signal<CriticalSectionRawMutex, Command>,
+}
+
+impl GateOut {
+    pub const fn new() -> Self {
+        Self {
+            commands: Signal::new(),
+        }
+    }
+
+    /// Set the gate high or low until the next command.
+    pub fn set(&self, high: bool) {
+        self.commands.signal(Command::Set(high));
+    }
+
+    /// Send a pulse of `width` now.
+    pub fn trigger(&self, width: Duration) {
+        self.trigger_at(Instant::now(), width);
+    }
+
+    /// Send a pulse of `width` at `at`. Only the latest command is kept,
+    /// so a pulse still to come is dropped by the next command.
+    pub fn trigger_at(&self, at: Instant, width: Duration) {
+        self.commands.signal(Command::Trigger { at, width });
+    }
+
+    /// From the audio callback, send a pulse at sample `offset` of the block
+    /// being rendered, so it lines up with the audio. The block is played
+    /// one block after the callback runs. The timing is as fine as the
+    /// embassy-time tick, about 1.5 samples at 48 kHz.
+    pub fn trigger_at_sample(&self, offset: usize, fs: Fs, width: Duration) {
+        let samples = (BLOCK_LENGTH + offset) as u64;
+        let delay = Duration::from_micros(samples * 1_000_000 / fs.into_hz() as u64);
+        self.trigger_at(Instant::now() + delay, width);
+    }
+
+    /// Drive `pin` by the commands, forever.
+    pub async fn run(&self, mut pin: GatePin<'_>) -> ! {
+        let mut schedule = GateSchedule::new();
+        loop {
+            pin.set(schedule.update(Instant::now()));
+            let deadline = schedule.next_deadline().unwrap_or(Instant::MAX);
+            if let Either::First(command) = select(self.commands.wait(), Timer::at(deadline)).await
+            {
+                schedule.command(command);
+            }
+        }
+    }
+}
+
+impl Default for GateOut {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,47 @@
+//! The user LED on PC7.
+//!
+//! [`UserLed`] switches it on and off. A [`PwmLed`], the user LED of a board
+//! made with `new_daisy_board!(p, pwm_led)`, has brightness control and is
+//! driven by a [`PatternPlayer`] to show a status without blocking other
+//! tasks:
+//!
+//! ```ignore
+//! static STATUS: PatternPlayer = PatternPlayer::new();
+//!
+//! #[embassy_executor::task]
+//! async fn status_led(led: PwmLed<'static>) {
+//!     STATUS.run(led).await;
+//! }
+//!
+//! let board = new_daisy_board!(p, pwm_led);
+//! spawner.spawn(status_led(board.user_led))?;
+//! STATUS.play(Pattern::Breathe { period: Duration::from_secs(2) });
+//! // later, from anywhere
+//! STATUS.play(Pattern::Morse("SOS"));
+//! ```
+
+use embassy_futures::select::{select, Either};
 use embassy_stm32 as hal;
-use hal::gpio::{self, Speed};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker};
+use hal::gpio::{self, OutputType, Speed};
+use hal::peripherals::{PC7, TIM3};
+use hal::time::khz;
+use hal::timer::simple_pwm::{PwmPin, SimplePwm};
+// on the host the std methods are used instead
+#[cfg(target_os = "none")]
+use micromath::F32Ext;
+
+/// Interval at which a [`PatternPlayer`] updates the brightness.
+const FRAME: Duration = Duration::from_millis(10);
+/// Length of a dot in [`Pattern::Morse`].
+const MORSE_UNIT: Duration = Duration::from_millis(150);
+/// On and off time of a blink in [`Pattern::Count`].
+const COUNT_BLINK: Duration = Duration::from_millis(200);
+/// Pause after the blinks of [`Pattern::Count`].
+const COUNT_PAUSE: Duration = Duration::from_millis(1000);
+
 pub struct UserLed<'a>(gpio::Output<'a>);
 
 impl UserLed<'_> {
@@ -13,3 +55,170 @@ impl UserLed<'_> {
         self.0.set_low();
     }
 }
+
+/// The LED driven with PWM on TIM3 channel 2, for brightness control.
+pub struct PwmLed<'d>(SimplePwm<'d, TIM3>);
+
+impl<'d> PwmLed<'d> {
+    pub fn new(pin: PC7, tim: TIM3) -> Self {
+        let pin = PwmPin::new_ch2(pin, OutputType::PushPull);
+        let mut pwm = SimplePwm::new(tim, None, Some(pin), None, None, khz(1), Default::default());
+        pwm.ch2().set_duty_cycle_fully_off();
+        pwm.ch2().enable();
+        Self(pwm)
+    }
+
+    /// Set the brightness from 0.0 (off) to 1.0 (full). The steps look even
+    /// to the eye, the duty cycle is the square of the brightness.
+    pub fn set_brightness(&mut self, brightness: f32) {
+        let brightness = brightness.clamp(0.0, 1.0);
+        let mut ch = self.0.ch2();
+        let duty = brightness * brightness * ch.max_duty_cycle() as f32;
+        ch.set_duty_cycle(duty as u16);
+    }
+
+    pub fn on(&mut self) {
+        self.0.ch2().set_duty_cycle_fully_on();
+    }
+
+    pub fn off(&mut self) {
+        self.0.ch2().set_duty_cycle_fully_off();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Pattern {
+    Off,
+    On,
+    Blink {
+        on: Duration,
+        off: Duration,
+    },
+    /// Fade in and out.
+    Breathe {
+        period: Duration,
+    },
+    /// Blink `n` times, pause, repeat, e.g. for error codes.
+    Count(u8),
+    /// Letters and digits in Morse code, repeated. Other characters are
+    /// skipped, spaces separate words.
+    Morse(&'static str),
+}
+
+impl Pattern {
+    /// Brightness `elapsed` after the pattern started.
+    pub fn brightness(&self, elapsed: Duration) -> f32 {
+        let t = elapsed.as_micros();
+        match *self {
+            Pattern::Off => 0.0,
+            Pattern::On => 1.0,
+            Pattern::Blink { on, off } => {
+                let period = (on + off).as_micros().max(1);
+                level(t % period < on.as_micros())
+            }
+            Pattern::Breathe { period } => {
+                let period = period.as_micros().max(1);
+                let phase = (t % period) as f32 / period as f32;
+                (1.0 - (phase * 2.0 * core::f32::consts::PI).cos()) / 2.0
+            }
+            Pattern::Count(n) => {
+                let blinks = n as u64 * 2 * COUNT_BLINK.as_micros();
+                let t = t % (blinks + COUNT_PAUSE.as_micros());
+                level(t < blinks && (t / COUNT_BLINK.as_micros()).is_multiple_of(2))
+            }
+            Pattern::Morse(text) => {
+                let units: u64 = morse_segments(text).map(|(_, units)| units as u64).sum();
+                if units == 0 {
+                    return 0.0;
+                }
+                let mut unit = (t / MORSE_UNIT.as_micros()) % units;
+                for (on, units) in morse_segments(text) {
+                    if unit < units as u64 {
+                        return level(on);
+                    }
+                    unit -= units as u64;
+                }
+                0.0
+            }
+        }
+    }
+}
+
+fn level(on: bool) -> f32 {
+    if on {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Dots, dashes and gaps of `text` as `(on, units)`, ending with the gap
+/// before it repeats.
+fn morse_segments(text: &str) -> impl Iterator<Item = (bool, u32)> + '_ {
+    text.chars()
+        .flat_map(|c| {
+            let code = morse_code(c).unwrap_or("");
+            let word_gap = (c == ' ').then_some((false, 4));
+            code.chars()
+                .flat_map(|symbol| [(true, if symbol == '-' { 3 } else { 1 }), (false, 1)])
+                // 3 units between letters, including the one after the symbol
+                .chain((!code.is_empty()).then_some((false, 2)))
+                .chain(word_gap)
+        })
+        .chain(core::iter::once((false, 4)))
+}
+
+fn morse_code(c: char) -> Option<&'static str> {
+    const LETTERS: [&str; 26] = [
+        ".-", "-...", "-.-.", "-..", ".", "..-.", "--.", "....", "..", ".---", "-.-", ".-..", "--",
+        "-.", "---", ".--.", "--.-", ".-.", "...", "-", "..-", "...-", ".--", "-..-", "-.--",
+        "--..",
+    ];
+    const DIGITS: [&str; 10] = [
+        "-----", ".----", "..---", "...--", "....-", ".....", "-....", "--...", "---..", "----.",
+    ];
+    match c.to_ascii_uppercase() {
+        c @ 'A'..='Z' => Some(LETTERS[c as usize - 'A' as usize]),
+        c @ '0'..='9' => Some(DIGITS[c as usize - '0' as usize]),
+        _ => None,
+    }
+}
+
+/// Plays [`Pattern`]s on a [`PwmLed`]. [`PatternPlayer::play`] never blocks,
+/// so status can be shown from any task or the audio callback.
+pub struct PatternPlayer {
+    patterns: Signal<CriticalSectionRawMutex, Pattern>,
+}
+
+impl PatternPlayer {
+    pub const fn new() -> Self {
+        Self {
+            patterns: Signal::new(),
+        }
+    }
+
+    /// Switch to `pattern`, starting from its beginning.
+    pub fn play(&self, pattern: Pattern) {
+        self.patterns.signal(pattern);
+    }
+
+    /// Drive `led`, forever.
+    pub async fn run(&self, mut led: PwmLed<'_>) -> ! {
+        let mut pattern = Pattern::Off;
+        let mut start = Instant::now();
+        let mut ticker = Ticker::every(FRAME);
+        loop {
+            led.set_brightness(pattern.brightness(Instant::now() - start));
+            if let Either::First(next) = select(self.patterns.wait(), ticker.next()).await {
+                pattern = next;
+                start = Instant::now();
+            }
+        }
+    }
+}
+
+impl Default for PatternPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
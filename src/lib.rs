@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 // use same configuration concept as https://github.com/zlosynth/daisy
 #[cfg(all(
@@ -35,19 +35,49 @@ compile_error!(
     "target board must be selected using a feature: \"seed_1_2\" | \"seed_1_1\" | \"seed\" | \"patch_sm\""
 );
 
+#[cfg(any(
+    all(feature = "boot_sram", feature = "boot_qspi"),
+    all(feature = "boot_sram", feature = "bootloader"),
+    all(feature = "boot_qspi", feature = "bootloader")
+))]
+compile_error!(
+    "only a single memory layout must be selected: \"boot_sram\" | \"boot_qspi\" | \"bootloader\""
+);
+
+pub mod adc;
 pub mod audio;
 pub mod board;
+pub mod buffer;
 pub mod codec;
+pub mod control;
+pub mod crc;
+pub mod cv;
+pub mod encoder;
 pub mod flash;
+pub mod gate;
 pub mod led;
+pub mod memory;
 pub mod pins;
+pub mod preset;
+pub mod samples;
 pub mod sdram;
+pub mod settings;
+pub mod switch;
+pub mod system;
+pub mod update;
 pub mod usb;
+pub mod voct;
 
 pub use board::DaisyBoard;
 pub use codec::{Codec, Pins as CodecPins};
 pub use embassy_stm32 as hal;
 
+// The time driver links in the embassy executor, whose pender the host
+// unit tests have to provide.
+#[cfg(test)]
+#[no_mangle]
+fn __pender(_context: *mut ()) {}
+
 pub fn default_rcc() -> hal::Config {
     let mut config = hal::Config::default();
     use hal::rcc::*;
@@ -63,7 +93,7 @@ pub fn default_rcc() -> hal::Config {
         source: PllSource::HSE,
         prediv: PllPreDiv::DIV4,
         mul: PllMul::MUL50,
-        divp: None,
+        divp: Some(PllDiv::DIV4),
         divq: None,
         divr: Some(PllDiv::DIV2),
     });
@@ -79,6 +109,7 @@ pub fn default_rcc() -> hal::Config {
     config.rcc.mux.fmcsel = hal::pac::rcc::vals::Fmcsel::PLL2_R; // 100MHz
     config.rcc.mux.sai1sel = hal::pac::rcc::vals::Saisel::PLL3_P; // 49.2MHz
     config.rcc.mux.usbsel = hal::pac::rcc::vals::Usbsel::PLL1_Q; // 48MHz
+    config.rcc.mux.adcsel = hal::pac::rcc::vals::Adcsel::PLL2_P; // 50MHz
     config.rcc.ahb_pre = AHBPrescaler::DIV2; // 240 MHz
     config.rcc.apb1_pre = APBPrescaler::DIV2; // 120 MHz
     config.rcc.apb2_pre = APBPrescaler::DIV2; // 120 MHz
@@ -122,9 +153,21 @@ macro_rules! codec_pins {
         }
     };
 }
+/// Hand the peripherals out as a [`DaisyBoard`](board::DaisyBoard).
+/// `new_daisy_board!(p, pwm_led)` drives the user LED with TIM3 as a
+/// [`PwmLed`](led::PwmLed) instead of a plain [`UserLed`](led::UserLed).
 #[macro_export]
 macro_rules! new_daisy_board {
     ($p:ident) => {
+        daisy_embassy::new_daisy_board!(@board $p, daisy_embassy::led::UserLed::new($p.PC7))
+    };
+    ($p:ident, pwm_led) => {
+        daisy_embassy::new_daisy_board!(
+            @board $p,
+            daisy_embassy::led::PwmLed::new($p.PC7, $p.TIM3)
+        )
+    };
+    (@board $p:ident, $user_led:expr) => {
         daisy_embassy::board::DaisyBoard {
             pins: daisy_embassy::pins::DaisyPins {
                 d0: $p.PB12,
@@ -159,7 +202,7 @@ macro_rules! new_daisy_board {
                 d29: $p.PB14,
                 d30: $p.PB15,
             },
-            user_led: daisy_embassy::led::UserLed::new($p.PC7),
+            user_led: $user_led,
 
             audio_peripherals: daisy_embassy::audio::AudioPeripherals {
                 codec: daisy_embassy::Codec {},
@@ -251,7 +294,7 @@ macro_rules! new_daisy_board {
                 },
                 usb_otg_fs: $p.USB_OTG_FS,
             },
-            boot: $p.PG3,
+            boot: daisy_embassy::switch::BootButton::new($p.PG3),
         }
     };
 }
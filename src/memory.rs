@@ -0,0 +1,200 @@
+//! Placement of code and data in fast RAM.
+//!
+//! Four memory layouts are available:
+//!
+//! | feature      | program runs from                 | `FLASH` region      |
+//! |--------------|-----------------------------------|---------------------|
+//! | (none)       | internal flash                    | `0x0800_0000`, 128K |
+//! | `boot_sram`  | AXI SRAM, copied by a boot stage  | `0x2400_0000`, 480K |
+//! | `boot_qspi`  | memory-mapped QSPI flash          | `0x9004_0000`, 7936K|
+//! | `bootloader` | internal flash, as a boot stage   | `0x0800_0000`, 128K |
+//!
+//! In every layout, code and data can be moved to zero-wait-state memory
+//! with these sections:
+//!
+//! | section       | memory          | content                                |
+//! |---------------|-----------------|----------------------------------------|
+//! | `.itcm_text`  | ITCM, 64K       | functions, see [`itcm!`]               |
+//! | `.sram_text`  | AXI SRAM        | functions                              |
+//! | `.dtcm_data`  | DTCM, 128K      | initialised statics, see [`dtcm!`]     |
+//! | `.dtcm_bss`   | DTCM            | zeroed statics, see [`dtcm_bss!`]      |
+//!
+//! The stack, `.data` and `.bss` are in the DTCM too, so `.dtcm_*` mostly
+//! matters for keeping state there when `RAM` is moved elsewhere.
+//!
+//! All of these are set up by [`init_sections`], which has to run before
+//! `.data` and `.bss` are initialised, so applications using them call it
+//! from their `#[pre_init]`. They are then ready before `main` like any other
+//! static.
+//!
+//! ```ignore
+//! #[cortex_m_rt::pre_init]
+//! unsafe fn pre_init() {
+//!     daisy_embassy::memory::init_sections();
+//! }
+//!
+//! daisy_embassy::itcm! {
+//!     fn process(block: &mut [u32]) { /* ... */ }
+//! }
+//! daisy_embassy::dtcm! {
+//!     static mut GAIN: f32 = 1.0;
+//! }
+//! daisy_embassy::dtcm_bss! {
+//!     static mut HISTORY: [f32; 4096];
+//! }
+//! ```
+//!
+//! In the `bootloader` layout, `SRAM` starts after the first 480K of the AXI
+//! SRAM, which the boot stage overwrites with the image it starts (see
+//! [`SRAM_LOAD_ADDRESS`](crate::update::SRAM_LOAD_ADDRESS)).
+//!
+//! The DMA controllers cannot reach the ITCM and DTCM, so audio and other
+//! DMA buffers must stay in the AXI SRAM, D2 SRAM or SDRAM.
+
+// Copies `.itcm_text`, `.sram_text` and `.dtcm_data` and zeroes
+// `.dtcm_bss`. It runs before RAM is initialised, so it cannot be Rust.
+// `.sram_text` is cleaned from the D-cache and the I-cache is invalidated,
+// in case a boot stage left the caches on.
+#[cfg(target_arch = "arm")]
+core::arch::global_asm!(
+    ".section .text.__daisy_init_sections, \"ax\"",
+    ".global __daisy_init_sections",
+    ".type __daisy_init_sections, %function",
+    ".thumb_func",
+    "__daisy_init_sections:",
+    "ldr r0, =__sitcm_text",
+    "ldr r1, =__eitcm_text",
+    "ldr r2, =__siitcm_text",
+    "0: cmp r0, r1",
+    "bhs 1f",
+    "ldr r3, [r2], #4",
+    "str r3, [r0], #4",
+    "b 0b",
+    "1: ldr r0, =__ssram_text",
+    "ldr r1, =__esram_text",
+    "ldr r2, =__sisram_text",
+    "2: cmp r0, r1",
+    "bhs 3f",
+    "ldr r3, [r2], #4",
+    "str r3, [r0], #4",
+    "b 2b",
+    // DCCMVAC, clean by address, one 32 byte line at a time
+    "3: ldr r0, =__ssram_text",
+    "bic r0, r0, #31",
+    "ldr r2, =0xE000EF68",
+    "4: cmp r0, r1",
+    "bhs 5f",
+    "str r0, [r2]",
+    "adds r0, r0, #32",
+    "b 4b",
+    "5: ldr r0, =__sdtcm_data",
+    "ldr r1, =__edtcm_data",
+    "ldr r2, =__sidtcm_data",
+    "6: cmp r0, r1",
+    "bhs 7f",
+    "ldr r3, [r2], #4",
+    "str r3, [r0], #4",
+    "b 6b",
+    "7: ldr r0, =__sdtcm_bss",
+    "ldr r1, =__edtcm_bss",
+    "movs r3, #0",
+    "8: cmp r0, r1",
+    "bhs 9f",
+    "str r3, [r0], #4",
+    "b 8b",
+    "9: dsb",
+    // ICIALLU
+    "ldr r2, =0xE000EF50",
+    "movs r3, #0",
+    "str r3, [r2]",
+    "dsb",
+    "isb",
+    "bx lr",
+    ".ltorg",
+);
+
+#[cfg(target_arch = "arm")]
+extern "C" {
+    fn __daisy_init_sections();
+}
+
+/// Copy `.itcm_text`, `.sram_text` and `.dtcm_data` to RAM and zero
+/// `.dtcm_bss`, see the [module documentation](self).
+///
+/// # Safety
+///
+/// Only call this from `#[pre_init]`: it overwrites the statics of these
+/// sections.
+#[inline(always)]
+pub unsafe fn init_sections() {
+    #[cfg(target_arch = "arm")]
+    __daisy_init_sections();
+}
+
+/// Put functions in the ITCM. They are never inlined, as that would move
+/// them back into the caller. They are copied there by
+/// [`init_sections`](crate::memory::init_sections).
+///
+/// ```ignore
+/// daisy_embassy::itcm! {
+///     pub fn biquad(state: &mut State, block: &mut [f32]) { /* ... */ }
+/// }
+/// ```
+#[macro_export]
+macro_rules! itcm {
+    ($($item:item)*) => {
+        $(
+            #[link_section = ".itcm_text"]
+            #[inline(never)]
+            $item
+        )*
+    };
+}
+
+/// Put statics in the DTCM. Each static is copied there from its initial
+/// value by [`init_sections`](crate::memory::init_sections), see
+/// [`dtcm_bss!`] for big zeroed buffers.
+///
+/// ```ignore
+/// daisy_embassy::dtcm! {
+///     static mut PHASE: f32 = 0.0;
+///     static COEFFICIENTS: [f32; 5] = [1.0, 0.0, 0.0, 0.0, 0.0];
+/// }
+/// ```
+#[macro_export]
+macro_rules! dtcm {
+    ($($item:item)*) => {
+        $(
+            #[link_section = ".dtcm_data"]
+            $item
+        )*
+    };
+}
+
+/// Put statics starting out as all zeros in the DTCM, zeroed by
+/// [`init_sections`](crate::memory::init_sections). Unlike [`dtcm!`], they
+/// take no space in the program image. The type must be valid when
+/// zeroed, which is checked at compile time.
+///
+/// ```ignore
+/// daisy_embassy::dtcm_bss! {
+///     static mut DELAY: [f32; 8192];
+///     static COUNTER: AtomicU32;
+/// }
+/// ```
+#[macro_export]
+macro_rules! dtcm_bss {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static mut $name:ident: $ty:ty; $($rest:tt)*) => {
+        $(#[$attr])*
+        #[link_section = ".dtcm_bss"]
+        $vis static mut $name: $ty = unsafe { ::core::mem::zeroed() };
+        $crate::dtcm_bss! { $($rest)* }
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty; $($rest:tt)*) => {
+        $(#[$attr])*
+        #[link_section = ".dtcm_bss"]
+        $vis static $name: $ty = unsafe { ::core::mem::zeroed() };
+        $crate::dtcm_bss! { $($rest)* }
+    };
+}
@@ -0,0 +1,469 @@
+//! Bank of typed presets kept in flash.
+//!
+//! Each slot uses two sectors holding alternating copies, so a save
+//! interrupted by a power loss leaves the previous copy intact. Every copy
+//! carries the version of the [`Preset`] it was written with and a CRC;
+//! copies written by older firmware are handed to [`Preset::migrate`].
+//!
+//! Loading and saving are async and yield between chunks and while the
+//! flash is busy, so both can run next to
+//! [`Interface::start`](crate::audio::Interface::start)
+//! without stalling the audio.
+//!
+//! ```ignore
+//! struct Patch { gain: f32, cutoff: f32 }
+//!
+//! impl Preset for Patch {
+//!     const VERSION: u16 = 2;
+//!     const SIZE: usize = 8;
+//!     fn encode(&self, buffer: &mut [u8]) {
+//!         buffer[0..4].copy_from_slice(&self.gain.to_le_bytes());
+//!         buffer[4..8].copy_from_slice(&self.cutoff.to_le_bytes());
+//!     }
+//!     fn decode(buffer: &[u8]) -> Option<Self> {
+//!         let gain = f32::from_le_bytes(buffer[0..4].try_into().ok()?);
+//!         let cutoff = f32::from_le_bytes(buffer[4..8].try_into().ok()?);
+//!         Some(Self { gain, cutoff })
+//!     }
+//!     fn migrate(version: u16, buffer: &[u8]) -> Option<Self> {
+//!         // version 1 only stored the gain
+//!         let gain = f32::from_le_bytes(buffer.get(0..4)?.try_into().ok()?);
+//!         (version == 1).then_some(Self { gain, cutoff: 1.0 })
+//!     }
+//! }
+//!
+//! let mut bank: PresetBank<_, Patch, 64> = PresetBank::new(flash, 0x70_0000..0x71_0000)?;
+//! bank.save(0, &patch).await?;
+//! let patch = bank.load(0).await?;
+//! ```
+
+use crate::crc::Crc32;
+use crate::flash::{erase_sector_async, program_async, Storage, SECTOR_SIZE};
+use core::marker::PhantomData;
+use core::ops::Range;
+use embassy_futures::yield_now;
+
+const MAGIC: u32 = 0x5352_5044; // "DPRS"
+const HEADER_SIZE: usize = 16;
+const COPIES: u32 = 2;
+const CHUNK_SIZE: usize = 64;
+
+/// Data that can be kept in a [`PresetBank`].
+pub trait Preset: Sized {
+    /// Bump this whenever the encoding changes.
+    const VERSION: u16;
+    /// Encoded size of the current version.
+    const SIZE: usize;
+
+    /// Serialize into `buffer`, which is [`Preset::SIZE`] bytes long.
+    fn encode(&self, buffer: &mut [u8]);
+
+    /// Deserialize data written with the current [`Preset::VERSION`].
+    fn decode(buffer: &[u8]) -> Option<Self>;
+
+    /// Convert data written by firmware with an older `version`. Returning
+    /// `None` makes [`PresetBank::load`] fail with [`Error::Decode`].
+    fn migrate(version: u16, buffer: &[u8]) -> Option<Self> {
+        let _ = (version, buffer);
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// The range is not sector aligned, lies outside of the storage or is
+    /// too small for a single slot.
+    InvalidRange,
+    InvalidSlot,
+    /// The stored preset is larger than the bank's buffer, or the current
+    /// [`Preset::SIZE`] does not fit into it.
+    TooLarge,
+    /// The slot was written by newer firmware.
+    UnsupportedVersion(u16),
+    /// [`Preset::decode`] or [`Preset::migrate`] rejected the stored data.
+    Decode,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    version: u16,
+    len: u16,
+    sequence: u32,
+}
+
+/// Fixed number of preset slots in a flash range. `MAX_SIZE` bounds the
+/// encoded size of any version of `T`.
+pub struct PresetBank<S, T, const MAX_SIZE: usize> {
+    storage: S,
+    start: u32,
+    slots: u32,
+    buffer: [u8; MAX_SIZE],
+    _preset: PhantomData<T>,
+}
+
+impl<S: Storage, T: Preset, const MAX_SIZE: usize> PresetBank<S, T, MAX_SIZE> {
+    /// Use `range` of `storage` for as many slots as fit; each slot takes
+    /// two sectors.
+    pub fn new(storage: S, range: Range<u32>) -> Result<Self, Error> {
+        if T::SIZE > MAX_SIZE || MAX_SIZE + HEADER_SIZE > SECTOR_SIZE as usize {
+            return Err(Error::TooLarge);
+        }
+        let slot_size = COPIES * SECTOR_SIZE;
+        if !range.start.is_multiple_of(SECTOR_SIZE)
+            || range.end > storage.capacity()
+            || range.end.saturating_sub(range.start) < slot_size
+        {
+            return Err(Error::InvalidRange);
+        }
+        Ok(Self {
+            storage,
+            start: range.start,
+            slots: (range.end - range.start) / slot_size,
+            buffer: [0; MAX_SIZE],
+            _preset: PhantomData,
+        })
+    }
+
+    pub fn slots(&self) -> u32 {
+        self.slots
+    }
+
+    /// Read the preset of `slot`, or `None` if it was never saved or has
+    /// been cleared.
+    pub async fn load(&mut self, slot: u32) -> Result<Option<T>, Error> {
+        let Some((copy, header)) = self.current(slot).await? else {
+            return Ok(None);
+        };
+        if header.version > T::VERSION {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+        let len = header.len as usize;
+        if len > MAX_SIZE {
+            return Err(Error::TooLarge);
+        }
+        let address = self.copy_base(slot, copy) + HEADER_SIZE as u32;
+        self.storage.read(address, &mut self.buffer[..len]);
+        let data = &self.buffer[..len];
+        let preset = match header.version {
+            version if version == T::VERSION => T::decode(data),
+            version => T::migrate(version, data),
+        };
+        preset.map(Some).ok_or(Error::Decode)
+    }
+
+    /// Write `preset` to `slot`. The previous content stays readable until
+    /// the new copy is completely written. This also replaces content that
+    /// [`PresetBank::load`] cannot read, e.g. one written by newer firmware.
+    pub async fn save(&mut self, slot: u32, preset: &T) -> Result<(), Error> {
+        let (copy, sequence) = match self.current(slot).await? {
+            Some((copy, header)) => ((copy + 1) % COPIES, header.sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+        let base = self.copy_base(slot, copy);
+        preset.encode(&mut self.buffer[..T::SIZE]);
+        let header = Header {
+            version: T::VERSION,
+            len: T::SIZE as u16,
+            sequence,
+        };
+        let header = encode_header(&header, &self.buffer[..T::SIZE]);
+
+        erase_sector_async(&mut self.storage, base).await;
+        // The header goes last: a copy without it is never picked up.
+        program_async(
+            &mut self.storage,
+            base + HEADER_SIZE as u32,
+            &self.buffer[..T::SIZE],
+        )
+        .await;
+        program_async(&mut self.storage, base, &header).await;
+        Ok(())
+    }
+
+    /// Erase both copies of `slot`.
+    pub async fn clear(&mut self, slot: u32) -> Result<(), Error> {
+        self.check_slot(slot)?;
+        for copy in 0..COPIES {
+            let base = self.copy_base(slot, copy);
+            erase_sector_async(&mut self.storage, base).await;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+
+    /// Newest valid copy of `slot`, whatever its version and size.
+    async fn current(&mut self, slot: u32) -> Result<Option<(u32, Header)>, Error> {
+        self.check_slot(slot)?;
+        let mut newest: Option<(u32, Header)> = None;
+        for copy in 0..COPIES {
+            let base = self.copy_base(slot, copy);
+            let Some(header) = self.read_valid_header(base).await else {
+                continue;
+            };
+            // Sequence numbers are compared with wrap-around in mind.
+            let is_newer = newest.is_none_or(|(_, newest)| {
+                (header.sequence.wrapping_sub(newest.sequence) as i32) > 0
+            });
+            if is_newer {
+                newest = Some((copy, header));
+            }
+        }
+        Ok(newest)
+    }
+
+    async fn read_valid_header(&mut self, base: u32) -> Option<Header> {
+        let mut raw = [0; HEADER_SIZE];
+        self.storage.read(base, &mut raw);
+        let magic = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+        let header = Header {
+            version: u16::from_le_bytes([raw[4], raw[5]]),
+            len: u16::from_le_bytes([raw[6], raw[7]]),
+            sequence: u32::from_le_bytes([raw[8], raw[9], raw[10], raw[11]]),
+        };
+        let crc = u32::from_le_bytes([raw[12], raw[13], raw[14], raw[15]]);
+        if magic != MAGIC || header.len as u32 > SECTOR_SIZE - HEADER_SIZE as u32 {
+            return None;
+        }
+
+        let mut check = Crc32::new();
+        check.update(&raw[..12]);
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut offset = 0;
+        while offset < header.len as usize {
+            let n = (header.len as usize - offset).min(CHUNK_SIZE);
+            self.storage
+                .read(base + (HEADER_SIZE + offset) as u32, &mut chunk[..n]);
+            check.update(&chunk[..n]);
+            offset += n;
+            yield_now().await;
+        }
+        (check.finish() == crc).then_some(header)
+    }
+
+    fn check_slot(&self, slot: u32) -> Result<(), Error> {
+        if slot < self.slots {
+            Ok(())
+        } else {
+            Err(Error::InvalidSlot)
+        }
+    }
+
+    fn copy_base(&self, slot: u32, copy: u32) -> u32 {
+        self.start + (slot * COPIES + copy) * SECTOR_SIZE
+    }
+}
+
+fn encode_header(header: &Header, data: &[u8]) -> [u8; HEADER_SIZE] {
+    let mut raw = [0; HEADER_SIZE];
+    raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    raw[4..6].copy_from_slice(&header.version.to_le_bytes());
+    raw[6..8].copy_from_slice(&header.len.to_le_bytes());
+    raw[8..12].copy_from_slice(&header.sequence.to_le_bytes());
+    let mut crc = Crc32::new();
+    crc.update(&raw[..12]);
+    crc.update(data);
+    raw[12..16].copy_from_slice(&crc.finish().to_le_bytes());
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::MemFlash;
+    use embassy_futures::block_on;
+
+    type Flash = MemFlash<{ 4 * 4096 }>;
+    const RANGE: Range<u32> = 0..4 * SECTOR_SIZE;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Patch {
+        gain: f32,
+        cutoff: f32,
+    }
+
+    impl Preset for Patch {
+        const VERSION: u16 = 2;
+        const SIZE: usize = 8;
+        fn encode(&self, buffer: &mut [u8]) {
+            buffer[0..4].copy_from_slice(&self.gain.to_le_bytes());
+            buffer[4..8].copy_from_slice(&self.cutoff.to_le_bytes());
+        }
+        fn decode(buffer: &[u8]) -> Option<Self> {
+            let gain = f32::from_le_bytes(buffer.get(0..4)?.try_into().ok()?);
+            let cutoff = f32::from_le_bytes(buffer.get(4..8)?.try_into().ok()?);
+            Some(Self { gain, cutoff })
+        }
+        fn migrate(version: u16, buffer: &[u8]) -> Option<Self> {
+            let gain = f32::from_le_bytes(buffer.get(0..4)?.try_into().ok()?);
+            (version == 1).then_some(Self { gain, cutoff: 1.0 })
+        }
+    }
+
+    /// The first version of [`Patch`], which only stored the gain.
+    #[derive(Debug, PartialEq)]
+    struct PatchV1(f32);
+
+    impl Preset for PatchV1 {
+        const VERSION: u16 = 1;
+        const SIZE: usize = 4;
+        fn encode(&self, buffer: &mut [u8]) {
+            buffer.copy_from_slice(&self.0.to_le_bytes());
+        }
+        fn decode(buffer: &[u8]) -> Option<Self> {
+            Some(Self(f32::from_le_bytes(buffer.try_into().ok()?)))
+        }
+    }
+
+    fn bank(storage: Flash) -> PresetBank<Flash, Patch, 16> {
+        PresetBank::new(storage, RANGE).unwrap()
+    }
+
+    #[test]
+    fn saves_and_loads_slots() {
+        let mut bank = bank(Flash::new());
+        assert_eq!(bank.slots(), 2);
+        let first = Patch {
+            gain: 0.5,
+            cutoff: 0.25,
+        };
+        let second = Patch {
+            gain: 1.0,
+            cutoff: 0.75,
+        };
+
+        assert_eq!(block_on(bank.load(0)), Ok(None));
+        block_on(bank.save(0, &first)).unwrap();
+        block_on(bank.save(1, &first)).unwrap();
+        block_on(bank.save(1, &second)).unwrap();
+        assert_eq!(block_on(bank.load(2)), Err(Error::InvalidSlot));
+
+        let mut bank = self::bank(bank.into_inner());
+        assert_eq!(block_on(bank.load(0)), Ok(Some(first)));
+        assert_eq!(block_on(bank.load(1)), Ok(Some(second)));
+        block_on(bank.clear(0)).unwrap();
+        assert_eq!(block_on(bank.load(0)), Ok(None));
+    }
+
+    #[test]
+    fn migrates_older_versions() {
+        let mut old: PresetBank<Flash, PatchV1, 16> = PresetBank::new(Flash::new(), RANGE).unwrap();
+        block_on(old.save(0, &PatchV1(0.5))).unwrap();
+
+        let mut bank = bank(old.into_inner());
+        let migrated = Patch {
+            gain: 0.5,
+            cutoff: 1.0,
+        };
+        assert_eq!(block_on(bank.load(0)), Ok(Some(migrated)));
+
+        // the new version is saved next to the old copy, and read back as is
+        let saved = Patch {
+            gain: 0.5,
+            cutoff: 0.5,
+        };
+        block_on(bank.save(0, &saved)).unwrap();
+        assert_eq!(block_on(bank.load(0)), Ok(Some(saved)));
+
+        // firmware that only knows version 1 refuses the newer data
+        let mut old: PresetBank<Flash, PatchV1, 16> =
+            PresetBank::new(bank.into_inner(), RANGE).unwrap();
+        assert_eq!(block_on(old.load(0)), Err(Error::UnsupportedVersion(2)));
+    }
+
+    #[test]
+    fn unreadable_slots_can_be_overwritten() {
+        /// A later version that shrank [`Patch`] again.
+        #[derive(Debug, PartialEq)]
+        struct PatchV3(f32);
+
+        impl Preset for PatchV3 {
+            const VERSION: u16 = 3;
+            const SIZE: usize = 4;
+            fn encode(&self, buffer: &mut [u8]) {
+                buffer.copy_from_slice(&self.0.to_le_bytes());
+            }
+            fn decode(buffer: &[u8]) -> Option<Self> {
+                Some(Self(f32::from_le_bytes(buffer.try_into().ok()?)))
+            }
+        }
+
+        let patch = Patch {
+            gain: 0.5,
+            cutoff: 0.25,
+        };
+        let mut bank = bank(Flash::new());
+        block_on(bank.save(0, &patch)).unwrap();
+
+        // a copy of a newer version
+        let mut old: PresetBank<Flash, PatchV1, 4> =
+            PresetBank::new(bank.into_inner(), RANGE).unwrap();
+        assert_eq!(block_on(old.load(0)), Err(Error::UnsupportedVersion(2)));
+        block_on(old.save(0, &PatchV1(1.0))).unwrap();
+        assert_eq!(block_on(old.load(0)), Ok(Some(PatchV1(1.0))));
+
+        // a copy larger than the buffer
+        let mut bank = self::bank(old.into_inner());
+        block_on(bank.save(1, &patch)).unwrap();
+        let mut new: PresetBank<Flash, PatchV3, 4> =
+            PresetBank::new(bank.into_inner(), RANGE).unwrap();
+        assert_eq!(block_on(new.load(1)), Err(Error::TooLarge));
+        block_on(new.save(1, &PatchV3(1.0))).unwrap();
+        assert_eq!(block_on(new.load(1)), Ok(Some(PatchV3(1.0))));
+    }
+
+    #[test]
+    fn torn_write_falls_back_to_the_previous_preset() {
+        let previous = Patch {
+            gain: 0.5,
+            cutoff: 0.25,
+        };
+        let next = Patch {
+            gain: 0.0,
+            cutoff: 0.0,
+        };
+        let mut bank = bank(Flash::new());
+        block_on(bank.save(0, &previous)).unwrap();
+        let mut storage = bank.into_inner();
+
+        // Replay the save of `next` into the second copy of slot 0, losing
+        // power before the header is complete.
+        let base = SECTOR_SIZE;
+        let mut data = [0; Patch::SIZE];
+        next.encode(&mut data);
+        let header = encode_header(
+            &Header {
+                version: Patch::VERSION,
+                len: Patch::SIZE as u16,
+                sequence: 1,
+            },
+            &data,
+        );
+        storage.erase_sector(base);
+        let mut bank = self::bank(storage);
+        assert_eq!(block_on(bank.load(0)), Ok(Some(previous)));
+
+        let mut storage = bank.into_inner();
+        storage.program(base + HEADER_SIZE as u32, &data);
+        let mut bank = self::bank(storage);
+        assert_eq!(block_on(bank.load(0)), Ok(Some(previous)));
+
+        let mut storage = bank.into_inner();
+        storage.program(base, &header[..HEADER_SIZE / 2]);
+        let mut bank = self::bank(storage);
+        assert_eq!(block_on(bank.load(0)), Ok(Some(previous)));
+
+        // once the header is there, the new preset wins
+        let mut storage = bank.into_inner();
+        storage.program(base, &header);
+        let mut bank = self::bank(storage);
+        assert_eq!(block_on(bank.load(0)), Ok(Some(next)));
+
+        // and the next save overwrites the older copy, not the newest one
+        block_on(bank.save(0, &previous)).unwrap();
+        let mut bank = self::bank(bank.into_inner());
+        assert_eq!(block_on(bank.load(0)), Ok(Some(previous)));
+    }
+}
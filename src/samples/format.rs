@@ -0,0 +1,205 @@
+//! Layout of a sample image. This file has no dependencies besides
+//! `crate::crc`, so the host-side packer in `tools/sample-packer` includes it
+//! as is.
+//!
+//! All values are little-endian. An image starts with a header, followed by
+//! the directory and the sample data:
+//!
+//! | offset            | size            | content                           |
+//! |-------------------|-----------------|-----------------------------------|
+//! | 0                 | [`HEADER_SIZE`] | [`ImageHeader`]                   |
+//! | [`HEADER_SIZE`]   | `n * ENTRY_SIZE`| `n` directory entries ([`Entry`]) |
+//! | ...               | ...             | sample data, [`DATA_ALIGN`]ed     |
+//!
+//! Sample data is stored interleaved, exactly as in the data chunk of a WAV
+//! file: signed integers, except for 8 bit which is unsigned, or IEEE floats.
+
+use crate::crc::Crc32;
+
+pub const MAGIC: u32 = 0x504D_5344; // "DSMP"
+pub const VERSION: u16 = 1;
+pub const HEADER_SIZE: usize = 16;
+pub const ENTRY_SIZE: usize = 64;
+pub const NAME_LEN: usize = 32;
+pub const DATA_ALIGN: u32 = 4;
+
+const FLAG_LOOP: u8 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    Int,
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub entry_count: u16,
+    /// CRC-32 of the whole directory.
+    pub directory_crc: u32,
+}
+
+impl ImageHeader {
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut raw = [0; HEADER_SIZE];
+        raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        raw[6..8].copy_from_slice(&self.entry_count.to_le_bytes());
+        raw[8..12].copy_from_slice(&self.directory_crc.to_le_bytes());
+        raw
+    }
+
+    /// `None` if `raw` is not a header of a supported image version.
+    pub fn decode(raw: &[u8; HEADER_SIZE]) -> Option<Self> {
+        let magic = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+        let version = u16::from_le_bytes([raw[4], raw[5]]);
+        (magic == MAGIC && version == VERSION).then(|| Self {
+            entry_count: u16::from_le_bytes([raw[6], raw[7]]),
+            directory_crc: u32::from_le_bytes([raw[8], raw[9], raw[10], raw[11]]),
+        })
+    }
+
+    /// Size of the header and directory, where sample data can start.
+    pub fn data_start(&self) -> u32 {
+        (HEADER_SIZE + self.entry_count as usize * ENTRY_SIZE) as u32
+    }
+}
+
+/// Directory entry describing one sample or wavetable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Zero-padded UTF-8 name.
+    pub name: [u8; NAME_LEN],
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+    pub format: SampleFormat,
+    /// Loop start and end in frames, end exclusive.
+    pub loop_points: Option<(u32, u32)>,
+    /// Start of the data, relative to the start of the image.
+    pub offset: u32,
+    /// Length of the data in bytes.
+    pub length: u32,
+    /// CRC-32 of the data.
+    pub crc: u32,
+}
+
+impl Entry {
+    /// Entry name, up to the first zero byte.
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|b| *b == 0).unwrap_or(NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    /// Copy `name` into a zero-padded name field, truncated at a character
+    /// boundary to [`NAME_LEN`] bytes.
+    pub fn encode_name(name: &str) -> [u8; NAME_LEN] {
+        let mut len = name.len().min(NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut raw = [0; NAME_LEN];
+        raw[..len].copy_from_slice(&name.as_bytes()[..len]);
+        raw
+    }
+
+    pub fn bytes_per_frame(&self) -> u32 {
+        self.channels as u32 * (self.bits_per_sample as u32 / 8)
+    }
+
+    pub fn frames(&self) -> u32 {
+        match self.bytes_per_frame() {
+            0 => 0,
+            bytes => self.length / bytes,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let mut raw = [0; ENTRY_SIZE];
+        let (loop_start, loop_end) = self.loop_points.unwrap_or((0, 0));
+        raw[0..32].copy_from_slice(&self.name);
+        raw[32..36].copy_from_slice(&self.sample_rate.to_le_bytes());
+        raw[36] = self.channels;
+        raw[37] = self.bits_per_sample;
+        raw[38] = match self.format {
+            SampleFormat::Int => 0,
+            SampleFormat::Float => 1,
+        };
+        raw[39] = if self.loop_points.is_some() {
+            FLAG_LOOP
+        } else {
+            0
+        };
+        raw[40..44].copy_from_slice(&loop_start.to_le_bytes());
+        raw[44..48].copy_from_slice(&loop_end.to_le_bytes());
+        raw[48..52].copy_from_slice(&self.offset.to_le_bytes());
+        raw[52..56].copy_from_slice(&self.length.to_le_bytes());
+        raw[56..60].copy_from_slice(&self.crc.to_le_bytes());
+        raw
+    }
+
+    /// `None` if the entry uses an unknown sample format.
+    pub fn decode(raw: &[u8; ENTRY_SIZE]) -> Option<Self> {
+        let word = |at: usize| u32::from_le_bytes([raw[at], raw[at + 1], raw[at + 2], raw[at + 3]]);
+        let format = match raw[38] {
+            0 => SampleFormat::Int,
+            1 => SampleFormat::Float,
+            _ => return None,
+        };
+        let bits_per_sample = raw[37];
+        let supported = match format {
+            SampleFormat::Int => matches!(bits_per_sample, 8 | 16 | 24 | 32),
+            SampleFormat::Float => bits_per_sample == 32,
+        };
+        if !supported {
+            return None;
+        }
+        let mut name = [0; NAME_LEN];
+        name.copy_from_slice(&raw[0..32]);
+        Some(Self {
+            name,
+            sample_rate: word(32),
+            channels: raw[36],
+            bits_per_sample,
+            format,
+            loop_points: (raw[39] & FLAG_LOOP != 0).then(|| (word(40), word(44))),
+            offset: word(48),
+            length: word(52),
+            crc: word(56),
+        })
+    }
+}
+
+/// CRC-32 over the encoded directory entries.
+pub fn directory_crc<'a>(entries: impl IntoIterator<Item = &'a [u8; ENTRY_SIZE]>) -> u32 {
+    let mut crc = Crc32::new();
+    for entry in entries {
+        crc.update(entry);
+    }
+    crc.finish()
+}
+
+/// Convert raw sample data of `entry` to `f32` in `-1.0..1.0`. Converts as
+/// many whole samples as fit into `output` and returns their number.
+pub fn decode_samples(entry: &Entry, raw: &[u8], output: &mut [f32]) -> usize {
+    let bytes = entry.bits_per_sample as usize / 8;
+    let mut count = 0;
+    for (sample, out) in raw.chunks_exact(bytes).zip(output.iter_mut()) {
+        *out = match (entry.format, bytes) {
+            (SampleFormat::Float, _) => {
+                f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]])
+            }
+            (SampleFormat::Int, 1) => (sample[0] as f32 - 128.0) / 128.0,
+            (SampleFormat::Int, 2) => i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32_768.0,
+            (SampleFormat::Int, 3) => {
+                // sign-extend through the top byte of an i32
+                (i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8) as f32 / 8_388_608.0
+            }
+            (SampleFormat::Int, _) => {
+                i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f32
+                    / 2_147_483_648.0
+            }
+        };
+        count += 1;
+    }
+    count
+}
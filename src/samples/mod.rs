@@ -0,0 +1,353 @@
+//! Read-only bank of samples and wavetables stored in flash.
+//!
+//! Images are built on the host from WAV files with `tools/sample-packer`
+//! and flashed to any sector-aligned address, then opened with
+//! [`SampleBank::new`]. See [`format`] for the layout.
+//!
+//! ```ignore
+//! let mut bank = SampleBank::new(board.flash.build(), 0x10_0000)?;
+//! let kick = bank.find("kick").ok_or(Error::NotFound)?;
+//! // copy the whole sample to SDRAM ...
+//! bank.read(&kick, 0, sdram_buffer);
+//! // ... or stream it block by block
+//! let mut reader = bank.reader(kick);
+//! let n = reader.read_f32(&mut block);
+//! ```
+
+pub mod format;
+
+use crate::crc::Crc32;
+use crate::flash::Storage;
+pub use format::{Entry, SampleFormat};
+use format::{ImageHeader, ENTRY_SIZE, HEADER_SIZE};
+
+const CHUNK_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// No image of a supported version at the given address.
+    NoImage,
+    /// The directory CRC does not match, or an entry points outside of the
+    /// storage.
+    CorruptDirectory,
+    NotFound,
+}
+
+pub struct SampleBank<S> {
+    storage: S,
+    base: u32,
+    entry_count: u16,
+}
+
+impl<S: Storage> SampleBank<S> {
+    /// Open the image at `base`, checking its directory.
+    pub fn new(mut storage: S, base: u32) -> Result<Self, Error> {
+        let mut raw = [0; HEADER_SIZE];
+        storage.read(base, &mut raw);
+        let header = ImageHeader::decode(&raw).ok_or(Error::NoImage)?;
+        let data_start = base.checked_add(header.data_start());
+        if data_start.is_none_or(|start| start > storage.capacity()) {
+            return Err(Error::CorruptDirectory);
+        }
+        let mut bank = Self {
+            storage,
+            base,
+            entry_count: header.entry_count,
+        };
+
+        let mut crc = Crc32::new();
+        for index in 0..bank.entry_count {
+            crc.update(&bank.raw_entry(index));
+        }
+        if crc.finish() != header.directory_crc {
+            return Err(Error::CorruptDirectory);
+        }
+        for index in 0..bank.entry_count {
+            let entry = bank.entry(index).ok_or(Error::CorruptDirectory)?;
+            let end = base as u64 + entry.offset as u64 + entry.length as u64;
+            if end > bank.storage.capacity() as u64 {
+                return Err(Error::CorruptDirectory);
+            }
+        }
+        Ok(bank)
+    }
+
+    pub fn len(&self) -> u16 {
+        self.entry_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entry_count == 0
+    }
+
+    /// Directory entry at `index`.
+    pub fn entry(&mut self, index: u16) -> Option<Entry> {
+        if index >= self.entry_count {
+            return None;
+        }
+        Entry::decode(&self.raw_entry(index))
+    }
+
+    /// First entry called `name`.
+    pub fn find(&mut self, name: &str) -> Option<Entry> {
+        (0..self.entry_count)
+            .filter_map(|index| self.entry(index))
+            .find(|entry| entry.name() == name)
+    }
+
+    /// Copy raw data of `entry` starting `offset` bytes in. Returns the
+    /// number of bytes read, which is less than `buffer.len()` at the end
+    /// of the entry.
+    pub fn read(&mut self, entry: &Entry, offset: u32, buffer: &mut [u8]) -> usize {
+        let available = entry.length.saturating_sub(offset) as usize;
+        let len = buffer.len().min(available);
+        if len > 0 {
+            self.storage
+                .read(self.base + entry.offset + offset, &mut buffer[..len]);
+        }
+        len
+    }
+
+    /// Check the data of `entry` against its CRC.
+    pub fn verify(&mut self, entry: &Entry) -> bool {
+        let mut crc = Crc32::new();
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut offset = 0;
+        loop {
+            let n = self.read(entry, offset, &mut chunk);
+            if n == 0 {
+                break;
+            }
+            crc.update(&chunk[..n]);
+            offset += n as u32;
+        }
+        crc.finish() == entry.crc
+    }
+
+    /// Stream the samples of `entry` from the start.
+    pub fn reader(&mut self, entry: Entry) -> SampleReader<'_, S> {
+        SampleReader {
+            bank: self,
+            entry,
+            position: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+
+    fn raw_entry(&mut self, index: u16) -> [u8; ENTRY_SIZE] {
+        let mut raw = [0; ENTRY_SIZE];
+        let address = self.base + (HEADER_SIZE + index as usize * ENTRY_SIZE) as u32;
+        self.storage.read(address, &mut raw);
+        raw
+    }
+}
+
+/// Sequential reader over the samples of one entry.
+pub struct SampleReader<'a, S> {
+    bank: &'a mut SampleBank<S>,
+    entry: Entry,
+    /// Read position in bytes.
+    position: u32,
+}
+
+impl<S: Storage> SampleReader<'_, S> {
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    /// Read position in frames.
+    pub fn position(&self) -> u32 {
+        match self.entry.bytes_per_frame() {
+            0 => 0,
+            bytes => self.position / bytes,
+        }
+    }
+
+    /// Move the read position to `frame`, e.g. to the loop start.
+    pub fn seek(&mut self, frame: u32) {
+        let position = frame.saturating_mul(self.entry.bytes_per_frame());
+        self.position = position.min(self.entry.length);
+    }
+
+    /// Read raw bytes, returning how many were read. `0` means the end of
+    /// the entry was reached.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let n = self.bank.read(&self.entry, self.position, buffer);
+        self.position += n as u32;
+        n
+    }
+
+    /// Read interleaved samples converted to `f32`, returning how many were
+    /// read.
+    pub fn read_f32(&mut self, output: &mut [f32]) -> usize {
+        let bytes = self.entry.bits_per_sample as usize / 8;
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut count = 0;
+        while count < output.len() {
+            let want = ((output.len() - count) * bytes).min(CHUNK_SIZE / bytes * bytes);
+            let n = self.read(&mut chunk[..want]);
+            if n == 0 {
+                break;
+            }
+            count += format::decode_samples(&self.entry, &chunk[..n], &mut output[count..]);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crc::crc32;
+    use crate::flash::{MemFlash, SECTOR_SIZE};
+    use format::DATA_ALIGN;
+
+    const BASE: u32 = SECTOR_SIZE;
+
+    fn entry(name: &str, channels: u8, bits_per_sample: u8, format: SampleFormat) -> Entry {
+        Entry {
+            name: Entry::encode_name(name),
+            sample_rate: 48_000,
+            channels,
+            bits_per_sample,
+            format,
+            loop_points: None,
+            offset: 0,
+            length: 0,
+            crc: 0,
+        }
+    }
+
+    /// Lay out an image the way `tools/sample-packer` does.
+    fn pack(samples: &[(Entry, &[u8])]) -> ([u8; 1024], usize) {
+        let mut header = ImageHeader {
+            entry_count: samples.len() as u16,
+            directory_crc: 0,
+        };
+        let mut image = [0; 1024];
+        let mut directory = [[0; ENTRY_SIZE]; 4];
+        let mut offset = header.data_start();
+        for ((entry, data), raw) in samples.iter().zip(directory.iter_mut()) {
+            offset = offset.next_multiple_of(DATA_ALIGN);
+            let start = offset as usize;
+            image[start..start + data.len()].copy_from_slice(data);
+            let entry = Entry {
+                offset,
+                length: data.len() as u32,
+                crc: crc32(data),
+                ..*entry
+            };
+            *raw = entry.encode();
+            offset += data.len() as u32;
+        }
+        let directory = &directory[..samples.len()];
+        header.directory_crc = format::directory_crc(directory);
+        image[..HEADER_SIZE].copy_from_slice(&header.encode());
+        for (index, raw) in directory.iter().enumerate() {
+            let start = HEADER_SIZE + index * ENTRY_SIZE;
+            image[start..start + ENTRY_SIZE].copy_from_slice(raw);
+        }
+        (image, offset as usize)
+    }
+
+    /// Repeats a small memory over the whole 32-bit address space.
+    struct Mirrored(MemFlash<{ 2 * 4096 }>);
+
+    impl Storage for Mirrored {
+        fn capacity(&self) -> u32 {
+            u32::MAX
+        }
+        fn read(&mut self, address: u32, buffer: &mut [u8]) {
+            self.0.read(address % self.0.capacity(), buffer);
+        }
+        fn begin_program_page(&mut self, address: u32, data: &[u8]) {
+            self.0.begin_program_page(address % self.0.capacity(), data);
+        }
+        fn begin_erase_sector(&mut self, address: u32) {
+            self.0.begin_erase_sector(address % self.0.capacity());
+        }
+        fn is_busy(&mut self) -> bool {
+            false
+        }
+    }
+
+    fn flash(image: &[u8]) -> MemFlash<{ 2 * 4096 }> {
+        let mut flash = MemFlash::new();
+        flash.program(BASE, image);
+        flash
+    }
+
+    #[test]
+    fn reads_packed_image() {
+        let mono: [u8; 6] = [0x00, 0x40, 0x00, 0xC0, 0xFF, 0x7F];
+        let stereo: [u8; 9] = [0x80, 0xC0, 0x40, 0x00, 0x80, 0xFF, 0x80, 0x80, 0x80];
+        let (image, len) = pack(&[
+            (entry("mono", 1, 16, SampleFormat::Int), &mono),
+            (entry("stereo", 2, 8, SampleFormat::Int), &stereo),
+        ]);
+        let mut bank = SampleBank::new(flash(&image[..len]), BASE).unwrap();
+        assert_eq!(bank.len(), 2);
+        assert!(bank.find("drums").is_none());
+
+        let mono = bank.find("mono").unwrap();
+        assert_eq!(mono.frames(), 3);
+        assert_eq!(mono.offset % DATA_ALIGN, 0);
+        assert!(bank.verify(&mono));
+        let mut reader = bank.reader(mono);
+        let mut samples = [0.0; 4];
+        assert_eq!(reader.read_f32(&mut samples), 3);
+        assert_eq!(samples[..3], [0.5, -0.5, 32_767.0 / 32_768.0]);
+        assert_eq!(reader.position(), 3);
+        assert_eq!(reader.read_f32(&mut samples), 0);
+        reader.seek(1);
+        assert_eq!(reader.read_f32(&mut samples[..1]), 1);
+        assert_eq!(samples[0], -0.5);
+
+        let stereo = bank.find("stereo").unwrap();
+        assert_eq!(stereo.frames(), 4);
+        assert!(bank.verify(&stereo));
+        let mut raw = [0; 16];
+        assert_eq!(bank.read(&stereo, 7, &mut raw), 2);
+        assert_eq!(raw[..2], [0x80, 0x80]);
+        let mut reader = bank.reader(stereo);
+        assert_eq!(reader.read_f32(&mut samples), 4);
+        assert_eq!(samples, [0.0, 0.5, -0.5, -1.0]);
+    }
+
+    #[test]
+    fn detects_corrupt_images() {
+        let data = [1, 2, 3, 4];
+        let (image, len) = pack(&[(entry("one", 1, 8, SampleFormat::Int), &data)]);
+
+        assert!(matches!(
+            SampleBank::new(MemFlash::<4096>::new(), 0),
+            Err(Error::NoImage)
+        ));
+
+        let mut directory = image;
+        directory[HEADER_SIZE] ^= 1;
+        assert!(matches!(
+            SampleBank::new(flash(&directory[..len]), BASE),
+            Err(Error::CorruptDirectory)
+        ));
+
+        // a huge directory near the end of the address space must not wrap
+        // around
+        let mut header = image;
+        header[6..8].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert!(matches!(
+            SampleBank::new(Mirrored(flash(&header[..len])), u32::MAX - 0xFFF),
+            Err(Error::CorruptDirectory)
+        ));
+
+        let mut bank = SampleBank::new(flash(&image[..len]), BASE).unwrap();
+        let one = bank.find("one").unwrap();
+        let mut flash = bank.into_inner();
+        flash.program(BASE + one.offset, &[0]);
+        let mut bank = SampleBank::new(flash, BASE).unwrap();
+        assert!(!bank.verify(&one));
+    }
+}
@@ -0,0 +1,572 @@
+pub mod self_test;
+
+use crate::pins::SdRamPins;
+use core::cell::UnsafeCell;
+use core::mem::{align_of, size_of, size_of_val, MaybeUninit};
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::peripheral::{MPU, SCB};
+use embassy_stm32 as hal;
+use embassy_time::Delay;
+use hal::fmc::Fmc;
+use hal::peripherals::FMC;
+pub use stm32_fmc::devices::as4c16m32msa_6::As4c16m32msa as FmcDevice;
+
+pub const SDRAM_SIZE: usize = 64 * 1024 * 1024;
+
+extern "C" {
+    static mut __sdram_bss_start: u32;
+    static mut __sdram_bss_end: u32;
+}
+
+#[cfg(feature = "heap")]
+#[global_allocator]
+static HEAP: embedded_alloc::LlffHeap = embedded_alloc::LlffHeap::empty();
+#[cfg(feature = "heap")]
+static HEAP_READY: AtomicBool = AtomicBool::new(false);
+
+/// Start of the SDRAM in the address space (FMC bank 1).
+pub const SDRAM_BASE: usize = 0xC000_0000;
+/// Size of a data cache line, the unit of [`clean_dcache`] and
+/// [`invalidate_dcache`].
+pub const CACHE_LINE: usize = 32;
+const MPU_REGIONS: usize = 16;
+
+/// How the core caches a memory region. Only relevant once the data cache
+/// is enabled with `SCB::enable_dcache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum CachePolicy {
+    /// Reads and writes are cached, writes reach the memory when a line is
+    /// evicted or cleaned. Fastest, but buffers shared with a DMA need
+    /// [`clean_dcache`] and [`invalidate_dcache`].
+    WriteBack,
+    /// Reads are cached, writes go all the way to the memory. A DMA can read
+    /// what the core wrote, but memory written by a DMA still has to be
+    /// invalidated.
+    WriteThrough,
+    /// Nothing is cached, e.g. for DMA buffers.
+    NonCacheable,
+}
+
+impl CachePolicy {
+    /// TEX, C and B bits of the MPU region attributes.
+    fn attributes(self) -> u32 {
+        let (tex, c, b) = match self {
+            Self::WriteBack => (0b000, 1, 1),
+            Self::WriteThrough => (0b000, 1, 0),
+            Self::NonCacheable => (0b001, 0, 0),
+        };
+        (tex << 19) | (c << 17) | (b << 16)
+    }
+}
+
+/// An extra MPU region, e.g. a non-cacheable DMA window inside the SDRAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct MpuRegion {
+    /// Start address, a multiple of `size`.
+    pub base: usize,
+    /// Size in bytes, a power of two of at least 32.
+    pub size: usize,
+    pub cache_policy: CachePolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct SdRamConfig {
+    /// Policy for the whole SDRAM.
+    pub cache_policy: CachePolicy,
+    /// Regions configured after the SDRAM one, up to 15. Where regions
+    /// overlap, the later one wins.
+    pub regions: &'static [MpuRegion],
+}
+
+impl Default for SdRamConfig {
+    fn default() -> Self {
+        SdRamConfig {
+            cache_policy: CachePolicy::WriteBack,
+            regions: &[],
+        }
+    }
+}
+
+pub struct SdRamBuilder {
+    pub pins: SdRamPins,
+    pub instance: FMC,
+}
+
+impl SdRamBuilder {
+    /// [`SdRamBuilder::build_with_config`] with the default config: the
+    /// SDRAM is cached write-back.
+    pub fn build(self, mpu: &mut MPU, scb: &mut SCB) -> SdRam {
+        self.build_with_config(mpu, scb, &SdRamConfig::default())
+    }
+
+    /// Configure the MPU and FMC, initialise the SDRAM and zero the
+    /// `.sdram_bss` section. The rest of the SDRAM is handed out by the
+    /// returned [`SdRam`].
+    ///
+    /// A non-cacheable DMA window at the start of the SDRAM, where the
+    /// first allocations end up:
+    ///
+    /// ```ignore
+    /// const DMA_WINDOW: MpuRegion = MpuRegion {
+    ///     base: SDRAM_BASE,
+    ///     size: 1024 * 1024,
+    ///     cache_policy: CachePolicy::NonCacheable,
+    /// };
+    /// let config = SdRamConfig {
+    ///     regions: &[DMA_WINDOW],
+    ///     ..Default::default()
+    /// };
+    /// let mut sdram = board.sdram.build_with_config(&mut core.MPU, &mut core.SCB, &config);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If a region is not aligned to its size or there are too many.
+    pub fn build_with_config(self, mpu: &mut MPU, scb: &mut SCB, config: &SdRamConfig) -> SdRam {
+        assert!(
+            config.regions.len() < MPU_REGIONS,
+            "the MPU has room for 15 extra regions"
+        );
+
+        // Configure MPU for external SDRAM
+        // Refer to ARM®v7-M Architecture Reference Manual ARM DDI 0403
+        // Version E.b Section B3.5
+        const MEMFAULTENA: u32 = 1 << 16;
+        unsafe {
+            /* Make sure outstanding transfers are done */
+            cortex_m::asm::dmb();
+
+            scb.shcsr.modify(|r| r & !MEMFAULTENA);
+
+            /* Disable the MPU and clear the control register*/
+            mpu.ctrl.write(0);
+        }
+
+        // Region 0 covers the whole SDRAM, the extra regions follow
+        let sdram_region = MpuRegion {
+            base: SDRAM_BASE,
+            size: SDRAM_SIZE,
+            cache_policy: config.cache_policy,
+        };
+        for (number, region) in core::iter::once(&sdram_region)
+            .chain(config.regions)
+            .enumerate()
+        {
+            configure_region(mpu, number as u32, region);
+        }
+
+        const MPU_ENABLE: u32 = 0x01;
+        const MPU_DEFAULT_MMAP_FOR_PRIVILEGED: u32 = 0x04;
+
+        // Enable
+        unsafe {
+            mpu.ctrl
+                .modify(|r| r | MPU_DEFAULT_MMAP_FOR_PRIVILEGED | MPU_ENABLE);
+
+            scb.shcsr.modify(|r| r | MEMFAULTENA);
+
+            // Ensure MPU settings take effect
+            cortex_m::asm::dsb();
+            cortex_m::asm::isb();
+        }
+
+        let Self { pins, instance } = self;
+        let mut sdram = Fmc::sdram_a13bits_d32bits_4banks_bank1(
+            instance,
+            // A0-A12
+            pins.ff0,
+            pins.ff1,
+            pins.ff2,
+            pins.ff3,
+            pins.ff4,
+            pins.ff5,
+            pins.ff12,
+            pins.ff13,
+            pins.ff14,
+            pins.ff15,
+            pins.gg0,
+            pins.gg1,
+            pins.gg2,
+            // BA0-BA1
+            pins.gg4,
+            pins.gg5,
+            // D0-D31
+            pins.dd14,
+            pins.dd15,
+            pins.dd0,
+            pins.dd1,
+            pins.ee7,
+            pins.ee8,
+            pins.ee9,
+            pins.ee10,
+            pins.ee11,
+            pins.ee12,
+            pins.ee13,
+            pins.ee14,
+            pins.ee15,
+            pins.dd8,
+            pins.dd9,
+            pins.dd10,
+            pins.hh8,
+            pins.hh9,
+            pins.hh10,
+            pins.hh11,
+            pins.hh12,
+            pins.hh13,
+            pins.hh14,
+            pins.hh15,
+            pins.ii0,
+            pins.ii1,
+            pins.ii2,
+            pins.ii3,
+            pins.ii6,
+            pins.ii7,
+            pins.ii9,
+            pins.ii10,
+            // NBL0 - NBL3
+            pins.ee0,
+            pins.ee1,
+            pins.ii4,
+            pins.ii5,
+            pins.hh2,  // SDCKE0
+            pins.gg8,  // SDCLK
+            pins.gg15, // SDNCAS
+            pins.hh3,  // SDNE0
+            pins.ff11, // SDRAS
+            pins.hh5,  // SDNWE
+            FmcDevice {},
+        );
+        let base = sdram.init(&mut Delay) as usize;
+
+        // SAFETY: the section is in the SDRAM, which is ready now, and
+        // `SdRamStatic`s in it are only handed out after `build`.
+        let bss_end = unsafe {
+            let mut word = addr_of_mut!(__sdram_bss_start);
+            let end = addr_of_mut!(__sdram_bss_end);
+            while word < end {
+                word.write_volatile(0);
+                word = word.add(1);
+            }
+            end as usize
+        };
+        SdRam {
+            next: bss_end.max(base),
+            end: base + SDRAM_SIZE,
+        }
+    }
+}
+
+/// The SDRAM could not hold an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct AllocError {
+    /// Bytes asked for, including alignment padding.
+    pub requested: usize,
+    /// Bytes left.
+    pub available: usize,
+}
+
+/// Types for which all zero bytes are a valid value.
+///
+/// # Safety
+///
+/// Only implement this for types without references, `NonZero*` fields,
+/// enums without a zero discriminant and other invalid zero patterns.
+pub unsafe trait Zeroable {}
+
+macro_rules! impl_zeroable {
+    ($($ty:ty),*) => {
+        $(unsafe impl Zeroable for $ty {})*
+    };
+}
+
+impl_zeroable!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+
+fn configure_region(mpu: &mut MPU, number: u32, region: &MpuRegion) {
+    const REGION_FULL_ACCESS: u32 = 0x03;
+    const REGION_ENABLE: u32 = 0x01;
+
+    assert!(
+        region.size.is_power_of_two() && region.size >= 32,
+        "MPU region size must be a power of 2 of 32 bytes or more"
+    );
+    assert!(
+        region.base.is_multiple_of(region.size),
+        "MPU region base must be aligned to its size"
+    );
+    // the SIZE field holds log2(size) - 1
+    let size = region.size.trailing_zeros() - 1;
+    unsafe {
+        mpu.rnr.write(number);
+        mpu.rbar.write(region.base as u32);
+        mpu.rasr.write(
+            (REGION_FULL_ACCESS << 24)
+                | region.cache_policy.attributes()
+                | (size << 1)
+                | REGION_ENABLE,
+        );
+    }
+}
+
+/// Write cached changes to `buffer` out to memory, e.g. before a DMA
+/// reads it.
+pub fn clean_dcache<T>(scb: &mut SCB, buffer: &[T]) {
+    scb.clean_dcache_by_slice(buffer);
+}
+
+/// Drop cached copies of `buffer`, e.g. after a DMA wrote it, so the core
+/// reads the new data.
+///
+/// # Panics
+///
+/// If `buffer` does not start and end on a [`CACHE_LINE`] boundary, as
+/// neighbouring data sharing a line would be lost. [`SdRam::align`] helps
+/// with that.
+pub fn invalidate_dcache<T>(scb: &mut SCB, buffer: &mut [T]) {
+    let start = buffer.as_ptr() as usize;
+    assert!(
+        start.is_multiple_of(CACHE_LINE) && size_of_val(buffer).is_multiple_of(CACHE_LINE),
+        "buffer must cover whole cache lines"
+    );
+    // SAFETY: only `buffer` shares these cache lines
+    unsafe { scb.invalidate_dcache_by_slice(buffer) };
+}
+
+/// [`clean_dcache`] and [`invalidate_dcache`] at once, for buffers a DMA
+/// both reads and writes.
+pub fn clean_invalidate_dcache<T>(scb: &mut SCB, buffer: &mut [T]) {
+    scb.clean_invalidate_dcache_by_address(buffer.as_ptr() as usize, size_of_val(buffer));
+}
+
+/// A static in the `.sdram_bss` section, declared with [`sdram_static!`].
+/// Its memory only exists once the SDRAM is initialised, so it is handed
+/// out by [`SdRamStatic::take`] in exchange for proof of that.
+pub struct SdRamStatic<T> {
+    taken: AtomicBool,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// SAFETY: the value is only ever handed out once
+unsafe impl<T: Send> Sync for SdRamStatic<T> {}
+
+impl<T: Zeroable> SdRamStatic<T> {
+    #[doc(hidden)]
+    /// # Safety
+    ///
+    /// Only for use by [`sdram_static!`], which places it in `.sdram_bss`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            taken: AtomicBool::new(false),
+            value: UnsafeCell::new(MaybeUninit::zeroed()),
+        }
+    }
+
+    /// The zeroed value, or `None` if it was taken before.
+    // SAFETY: the `&mut` comes from the `UnsafeCell`, not from `&self`, and
+    // the `taken` flag is swapped atomically, so it is handed out at most
+    // once however many threads or interrupts race for it.
+    #[allow(clippy::mut_from_ref)]
+    pub fn take(&'static self, _sdram: &SdRam) -> Option<&'static mut T> {
+        if self.taken.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        // SAFETY: `build` zeroed the section, all zeros is a valid `T` and
+        // this is the only reference ever handed out.
+        Some(unsafe { (*self.value.get()).assume_init_mut() })
+    }
+}
+
+/// Declare statics in the SDRAM. They are zeroed by [`SdRamBuilder::build`]
+/// and taken with [`SdRamStatic::take`]:
+///
+/// ```ignore
+/// daisy_embassy::sdram_static! {
+///     static DELAY_LINE: [f32; 48_000 * 4];
+/// }
+///
+/// let sdram = board.sdram.build(&mut core.MPU, &mut core.SCB);
+/// let delay_line: &'static mut [f32; 48_000 * 4] = DELAY_LINE.take(&sdram).unwrap();
+/// ```
+#[macro_export]
+macro_rules! sdram_static {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty;)*) => {
+        $(
+            $(#[$attr])*
+            #[link_section = ".sdram_bss"]
+            $vis static $name: $crate::sdram::SdRamStatic<$ty> =
+                unsafe { $crate::sdram::SdRamStatic::new() };
+        )*
+    };
+}
+
+/// The initialised SDRAM, handed out as `'static` buffers by a bump
+/// allocator. Memory is never freed, so allocate delay lines, loop buffers
+/// and the like once at startup.
+///
+/// ```ignore
+/// let mut sdram = board.sdram.build(&mut core.MPU, &mut core.SCB);
+/// let loop_buffer: &'static mut [u32] = sdram.alloc_slice(960_000)?;
+/// sdram.align(32); // e.g. cache line aligned for DMA
+/// let history: &'static mut [f32; 4096] = sdram.alloc_zeroed()?;
+/// ```
+///
+/// With the `heap` feature, part of the SDRAM can back the global
+/// allocator instead, see [`SdRam::init_heap`].
+pub struct SdRam {
+    next: usize,
+    end: usize,
+}
+
+impl SdRam {
+    /// Bytes left for allocations.
+    pub fn available(&self) -> usize {
+        self.end - self.next
+    }
+
+    /// Align the next allocation to `align` bytes, a power of two. Every
+    /// allocation is at least aligned for its type anyway.
+    pub fn align(&mut self, align: usize) {
+        assert!(align.is_power_of_two(), "alignment must be a power of 2");
+        self.next = self.next.next_multiple_of(align).min(self.end);
+    }
+
+    /// Give `size` bytes to the global allocator, so `alloc::vec::Vec`,
+    /// `Box` and friends work. Everything else in the SDRAM stays available
+    /// to the other methods.
+    ///
+    /// # Panics
+    ///
+    /// If the heap was already set up.
+    #[cfg(feature = "heap")]
+    pub fn init_heap(&mut self, size: usize) -> Result<(), AllocError> {
+        self.align(8);
+        let memory = self.alloc_uninit::<u8>(size)?;
+        assert!(
+            !HEAP_READY.swap(true, Ordering::AcqRel),
+            "the heap can only be set up once"
+        );
+        // SAFETY: the memory is never handed out by the bump allocator again
+        unsafe { HEAP.init(memory.as_mut_ptr() as usize, size) };
+        Ok(())
+    }
+
+    /// Run destructive [`self_test`]s on the SDRAM not handed out yet.
+    /// `config.range` holds byte offsets from the start of the SDRAM; the
+    /// part in use is left out. Best run right after [`SdRamBuilder::build`].
+    ///
+    /// With the data cache enabled, the cache line of every word is cleaned
+    /// and invalidated right after it is accessed, so each read comes from
+    /// the SDRAM whatever the [`CachePolicy`].
+    pub fn self_test(
+        &mut self,
+        scb: &mut SCB,
+        config: &self_test::Config,
+        progress: impl FnMut(self_test::Progress),
+    ) -> self_test::Report {
+        let base = self.end - SDRAM_SIZE;
+        let free = (self.next - base).next_multiple_of(4);
+        let mut config = config.clone();
+        config.range.start = config.range.start.max(free);
+        config.range.end = config.range.end.min(SDRAM_SIZE).max(config.range.start);
+        let mut memory = RawMemory {
+            base: base as *mut u32,
+            scb,
+            cached: SCB::dcache_enabled(),
+        };
+        self_test::run(&mut memory, &config, progress)
+    }
+
+    /// Allocate `len` values of `T` without initialising them.
+    pub fn alloc_uninit<T>(
+        &mut self,
+        len: usize,
+    ) -> Result<&'static mut [MaybeUninit<T>], AllocError> {
+        let start = self.next.next_multiple_of(align_of::<T>());
+        let requested = len
+            .checked_mul(size_of::<T>())
+            .and_then(|size| size.checked_add(start - self.next))
+            .unwrap_or(usize::MAX);
+        if requested > self.available() {
+            return Err(AllocError {
+                requested,
+                available: self.available(),
+            });
+        }
+        self.next += requested;
+        // SAFETY: the range is inside the SDRAM, aligned for `T` and never
+        // handed out again.
+        Ok(unsafe { core::slice::from_raw_parts_mut(start as *mut MaybeUninit<T>, len) })
+    }
+
+    /// Allocate `len` values of `T`, all zero.
+    pub fn alloc_slice<T: Zeroable>(&mut self, len: usize) -> Result<&'static mut [T], AllocError> {
+        let slice = self.alloc_uninit::<T>(len)?;
+        // SAFETY: all zeros is a valid `T`
+        unsafe {
+            core::ptr::write_bytes(slice.as_mut_ptr(), 0, len);
+            Ok(&mut *(slice as *mut [MaybeUninit<T>] as *mut [T]))
+        }
+    }
+
+    /// Allocate a single zeroed `T`, e.g. a big array or a struct of
+    /// buffers implementing [`Zeroable`].
+    pub fn alloc_zeroed<T: Zeroable>(&mut self) -> Result<&'static mut T, AllocError> {
+        let slice = self.alloc_slice::<T>(1)?;
+        Ok(&mut slice[0])
+    }
+
+    /// Allocate `len` copies of `value`.
+    pub fn alloc_slice_filled<T: Copy>(
+        &mut self,
+        len: usize,
+        value: T,
+    ) -> Result<&'static mut [T], AllocError> {
+        let slice = self.alloc_uninit::<T>(len)?;
+        for item in slice.iter_mut() {
+            item.write(value);
+        }
+        // SAFETY: every item was just initialised
+        Ok(unsafe { &mut *(slice as *mut [MaybeUninit<T>] as *mut [T]) })
+    }
+}
+
+/// The whole SDRAM, accessed with volatile reads and writes so every access
+/// reaches the bus.
+struct RawMemory<'a> {
+    base: *mut u32,
+    scb: &'a mut SCB,
+    /// Whether accesses go through the data cache and have to be flushed.
+    cached: bool,
+}
+
+impl RawMemory<'_> {
+    /// Write the line of word `index` back if dirty and drop it, so the
+    /// next access to it reaches the SDRAM again. A line kept across
+    /// accesses would hide faults that change neighbouring words.
+    fn flush(&mut self, index: usize) {
+        if self.cached {
+            let address = self.base.wrapping_add(index) as usize;
+            self.scb.clean_invalidate_dcache_by_address(address, 4);
+        }
+    }
+}
+
+impl self_test::Memory for RawMemory<'_> {
+    fn words(&self) -> usize {
+        SDRAM_SIZE / 4
+    }
+
+    fn read(&mut self, index: usize) -> u32 {
+        // SAFETY: `SdRam::self_test` only tests memory not handed out
+        let value = unsafe { self.base.add(index).read_volatile() };
+        self.flush(index);
+        value
+    }
+
+    fn write(&mut self, index: usize, value: u32) {
+        // SAFETY: as above
+        unsafe { self.base.add(index).write_volatile(value) };
+        self.flush(index);
+    }
+}
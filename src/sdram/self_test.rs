@@ -0,0 +1,444 @@
+//! Destructive memory tests to find assembly faults such as open or shorted
+//! data and address lines, or bad cells.
+//!
+//! The tests run on anything implementing [`Memory`]: the SDRAM through
+//! [`SdRam::self_test`](super::SdRam::self_test), which keeps the data cache
+//! out of the way, or a simulated memory. Other memories have to make sure
+//! every access reaches the memory, or the tests would check the cache.
+
+use core::ops::Range;
+
+/// Number of failures kept in a [`Report`]. All of them are counted.
+pub const MAX_FAILURES: usize = 8;
+/// Words between two progress reports.
+const PROGRESS_INTERVAL: usize = 1 << 16;
+
+const PATTERN: u32 = 0xAAAA_AAAA;
+const ANTI_PATTERN: u32 = 0x5555_5555;
+const ZEROS: u32 = 0x0000_0000;
+const ONES: u32 = 0xFFFF_FFFF;
+
+/// Word-addressed memory under test.
+pub trait Memory {
+    /// Size in 32-bit words.
+    fn words(&self) -> usize;
+    fn read(&mut self, index: usize) -> u32;
+    fn write(&mut self, index: usize, value: u32);
+}
+
+impl Memory for [u32] {
+    fn words(&self) -> usize {
+        self.len()
+    }
+
+    fn read(&mut self, index: usize) -> u32 {
+        self[index]
+    }
+
+    fn write(&mut self, index: usize, value: u32) {
+        self[index] = value;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Test {
+    /// Walking ones on a single word.
+    DataBus,
+    /// Power-of-two offsets, finding stuck or shorted address lines.
+    AddressBus,
+    /// March C- over every word, or every `stride`th word.
+    March,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum March {
+    /// Every word of the range.
+    Full,
+    /// Every `stride`th word, for a quicker check.
+    Partial { stride: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Byte offsets into the memory to test, word aligned.
+    pub range: Range<usize>,
+    pub data_bus: bool,
+    pub address_bus: bool,
+    pub march: Option<March>,
+}
+
+impl Config {
+    /// All tests on `range`.
+    pub fn full(range: Range<usize>) -> Self {
+        Self {
+            range,
+            data_bus: true,
+            address_bus: true,
+            march: Some(March::Full),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Progress {
+    pub test: Test,
+    /// Steps of the current test done, out of `total`.
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Failure {
+    pub test: Test,
+    /// Byte offset of the failing word.
+    pub offset: usize,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl Failure {
+    /// Bits that read back wrong.
+    pub fn bits(&self) -> u32 {
+        self.expected ^ self.actual
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub struct Report {
+    failures: [Option<Failure>; MAX_FAILURES],
+    /// All failures, including those not kept.
+    pub failure_count: usize,
+    /// Every bit that read back wrong at least once.
+    pub failing_bits: u32,
+}
+
+impl Report {
+    fn new() -> Self {
+        Self {
+            failures: [None; MAX_FAILURES],
+            failure_count: 0,
+            failing_bits: 0,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failure_count == 0
+    }
+
+    /// The first [`MAX_FAILURES`] failures.
+    pub fn failures(&self) -> impl Iterator<Item = &Failure> {
+        self.failures.iter().flatten()
+    }
+
+    fn record(&mut self, failure: Failure) {
+        if let Some(slot) = self.failures.get_mut(self.failure_count) {
+            *slot = Some(failure);
+        }
+        self.failure_count += 1;
+        self.failing_bits |= failure.bits();
+    }
+}
+
+/// Run the tests selected in `config`, reporting progress along the way.
+///
+/// # Panics
+///
+/// If the range is not word aligned or does not fit into `memory`.
+pub fn run<M: Memory + ?Sized>(
+    memory: &mut M,
+    config: &Config,
+    mut progress: impl FnMut(Progress),
+) -> Report {
+    let Range { start, end } = config.range;
+    assert!(
+        start.is_multiple_of(4) && end.is_multiple_of(4) && start <= end,
+        "range must be word aligned"
+    );
+    assert!(end / 4 <= memory.words(), "range outside of the memory");
+    let mut tester = Tester {
+        memory,
+        first: start / 4,
+        words: (end - start) / 4,
+        report: Report::new(),
+    };
+    if tester.words == 0 {
+        return tester.report;
+    }
+
+    if config.data_bus {
+        tester.data_bus();
+        progress(Progress {
+            test: Test::DataBus,
+            done: 1,
+            total: 1,
+        });
+    }
+    if config.address_bus {
+        tester.address_bus();
+        progress(Progress {
+            test: Test::AddressBus,
+            done: 1,
+            total: 1,
+        });
+    }
+    if let Some(march) = config.march {
+        let stride = match march {
+            March::Full => 1,
+            March::Partial { stride } => stride.max(1),
+        };
+        tester.march(stride, &mut progress);
+    }
+    tester.report
+}
+
+struct Tester<'a, M: ?Sized> {
+    memory: &'a mut M,
+    /// Index of the first word under test.
+    first: usize,
+    words: usize,
+    report: Report,
+}
+
+impl<M: Memory + ?Sized> Tester<'_, M> {
+    fn check(&mut self, test: Test, word: usize, expected: u32) {
+        let actual = self.memory.read(self.first + word);
+        if actual != expected {
+            self.report.record(Failure {
+                test,
+                offset: (self.first + word) * 4,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    fn write(&mut self, word: usize, value: u32) {
+        self.memory.write(self.first + word, value);
+    }
+
+    fn data_bus(&mut self) {
+        for bit in 0..32 {
+            self.write(0, 1 << bit);
+            self.check(Test::DataBus, 0, 1 << bit);
+        }
+    }
+
+    fn address_bus(&mut self) {
+        // A base word aligned to the largest power of two that still leaves
+        // room for `base + 2^(lines - 1)` in the range, so each of these
+        // differs from the base in exactly one address line. Lines above
+        // that cannot be told apart within the range and are not tested.
+        let end = self.first + self.words;
+        let (base, lines) = (0..usize::BITS as usize)
+            .rev()
+            .find_map(|lines| {
+                let base = self.first.checked_next_multiple_of(1 << lines)?;
+                let top = base.checked_add((1 << lines) >> 1)?;
+                (top < end).then_some((base, lines))
+            })
+            .unwrap_or((self.first, 0));
+
+        // offsets from `first` of the base and of every word with a single
+        // address bit set on top of it
+        let base = base - self.first;
+        let mut offsets = [base; usize::BITS as usize + 1];
+        for line in 0..lines {
+            offsets[line + 1] = base + (1 << line);
+        }
+        let list = &offsets[..lines + 1];
+
+        for &offset in list {
+            self.write(offset, PATTERN);
+        }
+        // a line stuck high aliases an offset onto another one
+        self.write(base, ANTI_PATTERN);
+        for &offset in &list[1..] {
+            self.check(Test::AddressBus, offset, PATTERN);
+        }
+        self.write(base, PATTERN);
+
+        // a line stuck low or shorted aliases it the other way round
+        for &tested in list {
+            self.write(tested, ANTI_PATTERN);
+            for &offset in list.iter().filter(|&&offset| offset != tested) {
+                self.check(Test::AddressBus, offset, PATTERN);
+            }
+            self.write(tested, PATTERN);
+        }
+    }
+
+    /// March C-: ⇕(w0); ⇑(r0,w1); ⇑(r1,w0); ⇓(r0,w1); ⇓(r1,w0); ⇕(r0)
+    fn march(&mut self, stride: usize, progress: &mut impl FnMut(Progress)) {
+        let count = self.words.div_ceil(stride);
+        let total = count * 6;
+        let mut done: usize = 0;
+        let mut step = |tester: &mut Self, phase: &mut dyn FnMut(&mut Self, usize), up: bool| {
+            for i in 0..count {
+                let i = if up { i } else { count - 1 - i };
+                phase(tester, i * stride);
+                done += 1;
+                if done.is_multiple_of(PROGRESS_INTERVAL) {
+                    progress(Progress {
+                        test: Test::March,
+                        done,
+                        total,
+                    });
+                }
+            }
+        };
+        step(self, &mut |t, w| t.write(w, ZEROS), true);
+        step(self, &mut |t, w| t.read_write(w, ZEROS, ONES), true);
+        step(self, &mut |t, w| t.read_write(w, ONES, ZEROS), true);
+        step(self, &mut |t, w| t.read_write(w, ZEROS, ONES), false);
+        step(self, &mut |t, w| t.read_write(w, ONES, ZEROS), false);
+        step(self, &mut |t, w| t.check(Test::March, w, ZEROS), true);
+        progress(Progress {
+            test: Test::March,
+            done: total,
+            total,
+        });
+    }
+
+    fn read_write(&mut self, word: usize, expected: u32, value: u32) {
+        self.check(Test::March, word, expected);
+        self.write(word, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORDS: usize = 256;
+
+    #[derive(Clone, Copy)]
+    enum Fault {
+        /// A data bit that always reads high.
+        StuckBit(u32),
+        /// An address line stuck low, aliasing every word with it set onto
+        /// the one without.
+        StuckLine(usize),
+        /// A rising write to `aggressor` inverts `victim`.
+        Coupling { aggressor: usize, victim: usize },
+    }
+
+    /// A simulated memory with one fault.
+    struct Faulty {
+        cells: [u32; WORDS],
+        fault: Fault,
+    }
+
+    impl Faulty {
+        fn new(fault: Fault) -> Self {
+            Self {
+                cells: [0; WORDS],
+                fault,
+            }
+        }
+
+        fn cell(&self, index: usize) -> usize {
+            match self.fault {
+                Fault::StuckLine(line) => index & !(1 << line),
+                _ => index,
+            }
+        }
+    }
+
+    impl Memory for Faulty {
+        fn words(&self) -> usize {
+            WORDS
+        }
+
+        fn read(&mut self, index: usize) -> u32 {
+            let value = self.cells[self.cell(index)];
+            match self.fault {
+                Fault::StuckBit(bit) => value | 1 << bit,
+                _ => value,
+            }
+        }
+
+        fn write(&mut self, index: usize, value: u32) {
+            let cell = self.cell(index);
+            if let Fault::Coupling { aggressor, victim } = self.fault {
+                if cell == aggressor && !self.cells[cell] & value != 0 {
+                    self.cells[victim] = !self.cells[victim];
+                }
+            }
+            self.cells[cell] = value;
+        }
+    }
+
+    fn march_only() -> Config {
+        Config {
+            data_bus: false,
+            address_bus: false,
+            ..Config::full(0..WORDS * 4)
+        }
+    }
+
+    #[test]
+    fn healthy_memory_passes() {
+        let mut progress = None;
+        let report = run(&mut [0u32; WORDS][..], &Config::full(0..WORDS * 4), |p| {
+            progress = Some(p)
+        });
+        assert!(report.passed());
+        assert_eq!(report.failures().count(), 0);
+        assert_eq!(
+            progress,
+            Some(Progress {
+                test: Test::March,
+                done: WORDS * 6,
+                total: WORDS * 6,
+            })
+        );
+    }
+
+    #[test]
+    fn finds_stuck_data_bit() {
+        let config = Config {
+            march: None,
+            ..Config::full(0..WORDS * 4)
+        };
+        let report = run(&mut Faulty::new(Fault::StuckBit(7)), &config, |_| {});
+        assert!(!report.passed());
+        assert_eq!(report.failing_bits, 1 << 7);
+        assert_eq!(
+            report.failures().next().map(|f| f.test),
+            Some(Test::DataBus)
+        );
+    }
+
+    #[test]
+    fn finds_aliased_address_line_in_unaligned_range() {
+        let config = Config {
+            range: 12..1000,
+            data_bus: false,
+            address_bus: true,
+            march: None,
+        };
+        let report = run(&mut Faulty::new(Fault::StuckLine(4)), &config, |_| {});
+        assert!(!report.passed());
+        assert!(report.failures().all(|f| f.test == Test::AddressBus));
+    }
+
+    #[test]
+    fn march_finds_coupling_faults_either_way() {
+        for (aggressor, victim) in [(10, 200), (200, 10)] {
+            let mut memory = Faulty::new(Fault::Coupling { aggressor, victim });
+            let report = run(&mut memory, &march_only(), |_| {});
+            assert!(!report.passed());
+            assert!(report.failures().all(|f| f.test == Test::March));
+            assert!(report.failures().any(|f| f.offset == victim * 4));
+        }
+    }
+
+    #[test]
+    fn keeps_the_first_failures_and_counts_all() {
+        let report = run(&mut Faulty::new(Fault::StuckBit(0)), &march_only(), |_| {});
+        // every word reads wrong in the three passes expecting zeros
+        assert_eq!(report.failure_count, WORDS * 3);
+        assert_eq!(report.failures().count(), MAX_FAILURES);
+        assert_eq!(report.failures().next().map(|f| f.offset), Some(0));
+    }
+}
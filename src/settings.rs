@@ -0,0 +1,535 @@
+//! Wear-levelled key/value store on top of a [`Storage`].
+//!
+//! The configured range is split into sectors used as a ring. Only one sector
+//! is active at a time; every write appends a CRC-checked record to it, and the
+//! newest record of a key wins. When the active sector is full, the live
+//! records are copied to the next sector of the ring, which then becomes
+//! active. This spreads erase cycles over the whole range, and an interrupted
+//! write or garbage collection never loses the previously stored values.
+//!
+//! ```ignore
+//! let flash = board.flash.build();
+//! let mut settings = Settings::new(flash, 0x7F_0000..0x80_0000)?;
+//! settings.write(KEY_VOLUME, &volume.to_le_bytes())?;
+//! let mut buf = [0; 4];
+//! if let Some(len) = settings.read(KEY_VOLUME, &mut buf)? { /* ... */ }
+//! ```
+
+use crate::crc::Crc32;
+use crate::flash::{Storage, SECTOR_SIZE};
+use core::ops::Range;
+
+const MAGIC: u32 = 0x5445_5344; // "DSET"
+const SECTOR_HEADER_SIZE: u32 = 8;
+const RECORD_HEADER_SIZE: u32 = 8;
+const ERASED_KEY: u16 = 0xFFFF;
+const ERASED_LEN: u16 = 0xFFFF;
+const CHUNK_SIZE: usize = 64;
+/// Keys indexed in the single pass over the log during garbage collection.
+/// Any further keys are looked up the slow way.
+const GC_INDEX_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// The range is not sector aligned, holds fewer than two sectors or lies
+    /// outside of the storage.
+    InvalidRange,
+    /// `0xFFFF` is reserved to detect erased flash.
+    InvalidKey,
+    /// The value does not fit into a single sector.
+    ValueTooLarge,
+    /// The buffer passed to [`Settings::read`] is shorter than the value.
+    BufferTooSmall,
+    /// The live values do not leave room for the new one, even after garbage
+    /// collection.
+    Full,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    address: u32,
+    key: u16,
+    len: u16,
+}
+
+impl Record {
+    fn size(&self) -> u32 {
+        RECORD_HEADER_SIZE + (self.len as u32).next_multiple_of(4)
+    }
+
+    fn data_address(&self) -> u32 {
+        self.address + RECORD_HEADER_SIZE
+    }
+
+    fn is_removal(&self) -> bool {
+        self.len == 0
+    }
+}
+
+enum Entry {
+    Record(Record),
+    /// Erased flash, ready for the next record.
+    Free,
+    /// A record header that cannot be trusted, left by an interrupted write.
+    Corrupt,
+    /// No room for another record header.
+    End,
+}
+
+pub struct Settings<S> {
+    storage: S,
+    start: u32,
+    sectors: u32,
+    active: u32,
+    sequence: u32,
+    write_address: u32,
+}
+
+impl<S: Storage> Settings<S> {
+    /// Mount the store kept in `range` of `storage`, formatting it if no
+    /// valid sector is found.
+    pub fn new(storage: S, range: Range<u32>) -> Result<Self, Error> {
+        if !range.start.is_multiple_of(SECTOR_SIZE)
+            || !range.end.is_multiple_of(SECTOR_SIZE)
+            || range.end > storage.capacity()
+            || range.end.saturating_sub(range.start) < 2 * SECTOR_SIZE
+        {
+            return Err(Error::InvalidRange);
+        }
+        let mut settings = Self {
+            storage,
+            start: range.start,
+            sectors: (range.end - range.start) / SECTOR_SIZE,
+            active: 0,
+            sequence: 0,
+            write_address: 0,
+        };
+
+        let mut newest = None;
+        for sector in 0..settings.sectors {
+            if let Some(sequence) = settings.sector_sequence(sector) {
+                if newest.is_none_or(|(_, newest)| sequence > newest) {
+                    newest = Some((sector, sequence));
+                }
+            }
+        }
+        match newest {
+            Some((sector, sequence)) => {
+                settings.active = sector;
+                settings.sequence = sequence;
+                settings.write_address = settings.find_write_address();
+            }
+            None => settings.format(),
+        }
+        Ok(settings)
+    }
+
+    /// Erase every value.
+    pub fn format(&mut self) {
+        for sector in 0..self.sectors {
+            self.storage.erase_sector(self.sector_base(sector));
+        }
+        self.active = 0;
+        self.sequence = 0;
+        self.write_sector_header(0, 0);
+        self.write_address = self.sector_base(0) + SECTOR_HEADER_SIZE;
+    }
+
+    /// Largest value that can be stored.
+    pub fn max_value_len(&self) -> usize {
+        (SECTOR_SIZE - SECTOR_HEADER_SIZE - RECORD_HEADER_SIZE) as usize
+    }
+
+    /// Copy the value of `key` into `buffer`, returning its length, or
+    /// `None` if the key has never been written or was removed.
+    pub fn read(&mut self, key: u16, buffer: &mut [u8]) -> Result<Option<usize>, Error> {
+        if key == ERASED_KEY {
+            return Err(Error::InvalidKey);
+        }
+        match self.find(key) {
+            Some(record) if !record.is_removal() => {
+                let len = record.len as usize;
+                if buffer.len() < len {
+                    return Err(Error::BufferTooSmall);
+                }
+                self.storage.read(record.data_address(), &mut buffer[..len]);
+                Ok(Some(len))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Store `value` under `key`. Writing the value already stored is a no-op
+    /// and does not wear the flash. An empty value removes the key.
+    pub fn write(&mut self, key: u16, value: &[u8]) -> Result<(), Error> {
+        if key == ERASED_KEY {
+            return Err(Error::InvalidKey);
+        }
+        if value.len() > self.max_value_len() {
+            return Err(Error::ValueTooLarge);
+        }
+        let unchanged = match self.find(key) {
+            Some(record) => record.len as usize == value.len() && self.data_equals(&record, value),
+            None => value.is_empty(),
+        };
+        if unchanged {
+            return Ok(());
+        }
+        self.append(key, value)
+    }
+
+    /// Remove `key` from the store.
+    pub fn remove(&mut self, key: u16) -> Result<(), Error> {
+        self.write(key, &[])
+    }
+
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+
+    fn append(&mut self, key: u16, value: &[u8]) -> Result<(), Error> {
+        let size = RECORD_HEADER_SIZE + (value.len() as u32).next_multiple_of(4);
+        if self.active_end() - self.write_address < size {
+            self.collect_garbage();
+            if self.active_end() - self.write_address < size {
+                return Err(Error::Full);
+            }
+        }
+
+        let len = value.len() as u16;
+        let mut crc = Crc32::new();
+        crc.update(&key.to_le_bytes());
+        crc.update(&len.to_le_bytes());
+        crc.update(value);
+        let mut header = [0; RECORD_HEADER_SIZE as usize];
+        header[0..2].copy_from_slice(&key.to_le_bytes());
+        header[2..4].copy_from_slice(&len.to_le_bytes());
+        header[4..8].copy_from_slice(&crc.finish().to_le_bytes());
+
+        self.storage.program(self.write_address, &header);
+        self.storage
+            .program(self.write_address + RECORD_HEADER_SIZE, value);
+        self.write_address += size;
+        Ok(())
+    }
+
+    /// Move the live records of the active sector to the next one of the
+    /// ring. The new sector only becomes valid once its header is written,
+    /// after all records have been copied.
+    fn collect_garbage(&mut self) {
+        let target = (self.active + 1) % self.sectors;
+        let target_base = self.sector_base(target);
+        self.storage.erase_sector(target_base);
+
+        // Latest valid record of the first keys, so each of their records
+        // is checked once instead of against the rest of the log.
+        let mut index = [(ERASED_KEY, 0u32); GC_INDEX_SIZE];
+        let mut indexed = 0;
+        let mut overflow = false;
+        let first = self.sector_base(self.active) + SECTOR_HEADER_SIZE;
+        let mut address = first;
+        while let Entry::Record(record) = self.entry_at(address, self.write_address) {
+            if self.is_valid(&record) {
+                match index[..indexed]
+                    .iter_mut()
+                    .find(|(key, _)| *key == record.key)
+                {
+                    Some(entry) => entry.1 = record.address,
+                    None if indexed < GC_INDEX_SIZE => {
+                        index[indexed] = (record.key, record.address);
+                        indexed += 1;
+                    }
+                    None => overflow = true,
+                }
+            }
+            address += record.size();
+        }
+
+        let mut write_address = target_base + SECTOR_HEADER_SIZE;
+        let mut address = first;
+        while let Entry::Record(record) = self.entry_at(address, self.write_address) {
+            let live = !record.is_removal()
+                && match index[..indexed].iter().find(|(key, _)| *key == record.key) {
+                    Some((_, latest)) => *latest == record.address,
+                    None => overflow && self.is_valid(&record) && self.is_latest(&record),
+                };
+            if live {
+                self.copy(record.address, write_address, record.size());
+                write_address += record.size();
+            }
+            address += record.size();
+        }
+
+        let sequence = self.sequence.wrapping_add(1);
+        self.write_sector_header(target, sequence);
+        self.active = target;
+        self.sequence = sequence;
+        self.write_address = write_address;
+    }
+
+    /// Newest valid record of `key` in the active sector. Only the records
+    /// of `key` are checked against their CRC.
+    fn find(&mut self, key: u16) -> Option<Record> {
+        let mut found = None;
+        let mut address = self.sector_base(self.active) + SECTOR_HEADER_SIZE;
+        while let Entry::Record(record) = self.entry_at(address, self.write_address) {
+            if record.key == key && self.is_valid(&record) {
+                found = Some(record);
+            }
+            address += record.size();
+        }
+        found
+    }
+
+    fn is_latest(&mut self, record: &Record) -> bool {
+        let mut address = record.address + record.size();
+        while let Entry::Record(newer) = self.entry_at(address, self.write_address) {
+            if newer.key == record.key && self.is_valid(&newer) {
+                return false;
+            }
+            address += newer.size();
+        }
+        true
+    }
+
+    fn entry_at(&mut self, address: u32, end: u32) -> Entry {
+        if end.saturating_sub(address) < RECORD_HEADER_SIZE {
+            return Entry::End;
+        }
+        let mut header = [0; RECORD_HEADER_SIZE as usize];
+        self.storage.read(address, &mut header);
+        if header.iter().all(|b| *b == 0xFF) {
+            return Entry::Free;
+        }
+        let key = u16::from_le_bytes([header[0], header[1]]);
+        let len = u16::from_le_bytes([header[2], header[3]]);
+        let record = Record { address, key, len };
+        if key == ERASED_KEY || len == ERASED_LEN || record.size() > end - address {
+            return Entry::Corrupt;
+        }
+        Entry::Record(record)
+    }
+
+    /// Check the data of `record` against the CRC in its header.
+    fn is_valid(&mut self, record: &Record) -> bool {
+        let mut crc = [0; 4];
+        self.storage.read(record.address + 4, &mut crc);
+        let mut check = Crc32::new();
+        check.update(&record.key.to_le_bytes());
+        check.update(&record.len.to_le_bytes());
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut offset = 0;
+        while offset < record.len as u32 {
+            let n = (record.len as u32 - offset).min(CHUNK_SIZE as u32) as usize;
+            self.storage
+                .read(record.data_address() + offset, &mut chunk[..n]);
+            check.update(&chunk[..n]);
+            offset += n as u32;
+        }
+        check.finish() == u32::from_le_bytes(crc)
+    }
+
+    /// Skip the records of the active sector. An interrupted write leaves a
+    /// corrupt header or stray programmed bytes; the rest of the sector is
+    /// then considered full, so the next write moves on to a clean sector.
+    fn find_write_address(&mut self) -> u32 {
+        let end = self.active_end();
+        let mut address = self.sector_base(self.active) + SECTOR_HEADER_SIZE;
+        loop {
+            match self.entry_at(address, end) {
+                Entry::Record(record) => address += record.size(),
+                Entry::Free if self.is_erased(address, end) => return address,
+                _ => return end,
+            }
+        }
+    }
+
+    fn is_erased(&mut self, mut address: u32, end: u32) -> bool {
+        let mut chunk = [0; CHUNK_SIZE];
+        while address < end {
+            let n = (end - address).min(CHUNK_SIZE as u32) as usize;
+            self.storage.read(address, &mut chunk[..n]);
+            if chunk[..n].iter().any(|b| *b != 0xFF) {
+                return false;
+            }
+            address += n as u32;
+        }
+        true
+    }
+
+    fn data_equals(&mut self, record: &Record, value: &[u8]) -> bool {
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut address = record.data_address();
+        for expected in value.chunks(CHUNK_SIZE) {
+            let actual = &mut chunk[..expected.len()];
+            self.storage.read(address, actual);
+            if actual != expected {
+                return false;
+            }
+            address += expected.len() as u32;
+        }
+        true
+    }
+
+    fn copy(&mut self, from: u32, to: u32, size: u32) {
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut offset = 0;
+        while offset < size {
+            let n = (size - offset).min(CHUNK_SIZE as u32) as usize;
+            self.storage.read(from + offset, &mut chunk[..n]);
+            self.storage.program(to + offset, &chunk[..n]);
+            offset += n as u32;
+        }
+    }
+
+    fn sector_sequence(&mut self, sector: u32) -> Option<u32> {
+        let mut header = [0; SECTOR_HEADER_SIZE as usize];
+        self.storage.read(self.sector_base(sector), &mut header);
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        (magic == MAGIC && sequence != u32::MAX).then_some(sequence)
+    }
+
+    fn write_sector_header(&mut self, sector: u32, sequence: u32) {
+        let mut header = [0; SECTOR_HEADER_SIZE as usize];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&sequence.to_le_bytes());
+        self.storage.program(self.sector_base(sector), &header);
+    }
+
+    fn sector_base(&self, sector: u32) -> u32 {
+        self.start + sector * SECTOR_SIZE
+    }
+
+    fn active_end(&self) -> u32 {
+        self.sector_base(self.active) + SECTOR_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::MemFlash;
+
+    const RANGE: Range<u32> = 0..3 * SECTOR_SIZE;
+
+    fn mount(storage: MemFlash<{ 3 * 4096 }>) -> Settings<MemFlash<{ 3 * 4096 }>> {
+        Settings::new(storage, RANGE).unwrap()
+    }
+
+    #[test]
+    fn rejects_bad_ranges_and_keys() {
+        let storage = MemFlash::<{ 3 * 4096 }>::new;
+        assert!(matches!(
+            Settings::new(storage(), 1..3 * SECTOR_SIZE),
+            Err(Error::InvalidRange)
+        ));
+        assert!(matches!(
+            Settings::new(storage(), 0..SECTOR_SIZE),
+            Err(Error::InvalidRange)
+        ));
+        assert!(matches!(
+            Settings::new(storage(), 0..4 * SECTOR_SIZE),
+            Err(Error::InvalidRange)
+        ));
+
+        let mut settings = mount(storage());
+        assert_eq!(settings.write(ERASED_KEY, b"x"), Err(Error::InvalidKey));
+        let value = [0; SECTOR_SIZE as usize];
+        assert_eq!(settings.write(1, &value), Err(Error::ValueTooLarge));
+        let mut buf = [0; 2];
+        settings.write(1, b"long").unwrap();
+        assert_eq!(settings.read(1, &mut buf), Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    fn survives_garbage_collection_and_remount() {
+        let mut settings = mount(MemFlash::new());
+        let mut buf = [0; 8];
+
+        settings.write(1, b"preset").unwrap();
+        settings.write(2, b"gone").unwrap();
+        settings.remove(2).unwrap();
+        // enough writes to wrap around the sector ring several times
+        for i in 0..2000u32 {
+            settings.write(3, &i.to_le_bytes()).unwrap();
+        }
+
+        let mut settings = mount(settings.into_inner());
+        assert_eq!(settings.read(1, &mut buf), Ok(Some(6)));
+        assert_eq!(&buf[..6], b"preset");
+        assert_eq!(settings.read(2, &mut buf), Ok(None));
+        assert_eq!(settings.read(3, &mut buf), Ok(Some(4)));
+        assert_eq!(&buf[..4], &1999u32.to_le_bytes());
+    }
+
+    #[test]
+    fn keeps_keys_beyond_the_garbage_collection_index() {
+        let mut settings = mount(MemFlash::new());
+        let keys = GC_INDEX_SIZE as u16 * 2;
+        for round in 0..20u16 {
+            for key in 0..keys {
+                settings.write(key, &(key ^ round).to_le_bytes()).unwrap();
+            }
+        }
+
+        let mut settings = mount(settings.into_inner());
+        let mut buf = [0; 2];
+        for key in 0..keys {
+            assert_eq!(settings.read(key, &mut buf), Ok(Some(2)));
+            assert_eq!(buf, (key ^ 19).to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn stores_share_one_storage() {
+        let mut storage = MemFlash::<{ 4 * 4096 }>::new();
+        let mut first = Settings::new(&mut storage, 0..2 * SECTOR_SIZE).unwrap();
+        first.write(1, b"first").unwrap();
+        let mut second = Settings::new(&mut storage, 2 * SECTOR_SIZE..4 * SECTOR_SIZE).unwrap();
+        second.write(1, b"second").unwrap();
+
+        let mut buf = [0; 8];
+        let mut first = Settings::new(&mut storage, 0..2 * SECTOR_SIZE).unwrap();
+        assert_eq!(first.read(1, &mut buf), Ok(Some(5)));
+        assert_eq!(&buf[..5], b"first");
+    }
+
+    #[test]
+    fn unchanged_values_are_not_written_again() {
+        let mut settings = mount(MemFlash::new());
+        settings.write(1, b"same").unwrap();
+        let write_address = settings.write_address;
+        settings.write(1, b"same").unwrap();
+        settings.remove(2).unwrap();
+        assert_eq!(settings.write_address, write_address);
+    }
+
+    #[test]
+    fn interrupted_write_keeps_the_previous_value() {
+        let mut settings = mount(MemFlash::new());
+        settings.write(1, b"old").unwrap();
+
+        // a header whose data never made it to the flash
+        let address = settings.write_address;
+        let mut header = [0; RECORD_HEADER_SIZE as usize];
+        header[0..2].copy_from_slice(&1u16.to_le_bytes());
+        header[2..4].copy_from_slice(&3u16.to_le_bytes());
+        settings.storage_mut().program(address, &header);
+
+        let mut settings = mount(settings.into_inner());
+        let mut buf = [0; 4];
+        assert_eq!(settings.read(1, &mut buf), Ok(Some(3)));
+        assert_eq!(&buf[..3], b"old");
+
+        // the torn record is skipped by later writes and reads
+        settings.write(1, b"new").unwrap();
+        let mut settings = mount(settings.into_inner());
+        assert_eq!(settings.read(1, &mut buf), Ok(Some(3)));
+        assert_eq!(&buf[..3], b"new");
+    }
+}
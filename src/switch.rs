@@ -0,0 +1,361 @@
+//! Debounced buttons, switches and gate inputs on any of the
+//! [`DaisyPins`](crate::pins::DaisyPins).
+//!
+//! A [`Switch`] turns a bouncing contact into clean [`Event`]s: presses,
+//! releases with the time held, long presses and double presses. It can be
+//! awaited from a task, or polled with [`Switch::update`], e.g. once per
+//! block in the audio callback:
+//!
+//! ```ignore
+//! let mut record = Switch::new(board.pins.d16, p.EXTI3, Default::default());
+//! loop {
+//!     match record.wait_for_event().await {
+//!         Event::Pressed => RECORD.store(true, Ordering::Relaxed),
+//!         Event::LongPress => CLEAR.store(true, Ordering::Relaxed),
+//!         _ => {}
+//!     }
+//! }
+//! ```
+//!
+//! A [`GateIn`] does the same for gate and trigger signals, reporting rising
+//! and falling [`Edge`]s.
+//!
+//! The [`BootButton`] on the board is a switch too, and can start the ST
+//! ROM bootloader.
+//!
+//! The timing is done by [`SwitchState`], which works on any reading and
+//! time stamp.
+
+use crate::pins::Boot;
+use embassy_futures::select::select;
+use embassy_stm32 as hal;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use hal::exti::ExtiInput;
+use hal::gpio::{Input, Pin, Pull};
+use hal::Peripheral;
+
+/// Longest wait without looking at the pin, in case an edge slipped in
+/// between reading the pin and waiting for the next edge.
+pub(crate) const IDLE_POLL: Duration = Duration::from_millis(100);
+/// Sampling interval of the [`BootButton`].
+const BOOT_POLL: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Event {
+    Pressed,
+    Released {
+        held: Duration,
+    },
+    /// Held for [`SwitchConfig::long_press`], sent once while still held.
+    LongPress,
+    /// Pressed again within [`SwitchConfig::double_press`] of the last
+    /// release, sent right after the second [`Event::Pressed`].
+    DoublePress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct SwitchConfig {
+    pub pull: Pull,
+    /// Pressed when the pin is low, for a switch to ground with a pull-up.
+    pub active_low: bool,
+    /// Changes within this time of the last one are taken as bounces.
+    pub debounce: Duration,
+    pub long_press: Option<Duration>,
+    pub double_press: Option<Duration>,
+}
+
+impl Default for SwitchConfig {
+    fn default() -> Self {
+        SwitchConfig {
+            pull: Pull::Up,
+            active_low: true,
+            debounce: Duration::from_millis(20),
+            long_press: Some(Duration::from_millis(800)),
+            double_press: Some(Duration::from_millis(300)),
+        }
+    }
+}
+
+/// Debouncing and event detection, independent of the pin.
+pub struct SwitchState {
+    config: SwitchConfig,
+    pressed: bool,
+    /// Last accepted change.
+    changed_at: Instant,
+    /// Last release, while a second press would be a double press.
+    released_at: Option<Instant>,
+    /// The current press is the second one of a double press.
+    double_press: bool,
+    long_press_sent: bool,
+    pending: Option<Event>,
+}
+
+impl SwitchState {
+    pub const fn new(config: SwitchConfig) -> Self {
+        Self {
+            config,
+            pressed: false,
+            changed_at: Instant::from_ticks(0),
+            released_at: None,
+            double_press: false,
+            long_press_sent: false,
+            pending: None,
+        }
+    }
+
+    pub fn config(&self) -> &SwitchConfig {
+        &self.config
+    }
+
+    /// Debounced state.
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// How long the switch has been held, `None` if it is released.
+    pub fn pressed_for(&self, now: Instant) -> Option<Duration> {
+        self.pressed.then(|| now - self.changed_at)
+    }
+
+    /// Feed a reading, `true` for pressed, and get the next event.
+    ///
+    /// A change is taken at once, unless it comes within
+    /// [`SwitchConfig::debounce`] of the previous one; then it is taken by
+    /// the first update after that time, if it still holds.
+    pub fn update(&mut self, pressed: bool, now: Instant) -> Option<Event> {
+        if let Some(event) = self.pending.take() {
+            return Some(event);
+        }
+
+        if pressed != self.pressed && now - self.changed_at >= self.config.debounce {
+            let held = now - self.changed_at;
+            self.pressed = pressed;
+            self.changed_at = now;
+            if !pressed {
+                // a third press does not make another double press
+                self.released_at = (!self.double_press).then_some(now);
+                return Some(Event::Released { held });
+            }
+
+            self.long_press_sent = false;
+            self.double_press = self
+                .released_at
+                .take()
+                .zip(self.config.double_press)
+                .is_some_and(|(released_at, window)| now - released_at <= window);
+            if self.double_press {
+                self.pending = Some(Event::DoublePress);
+            }
+            return Some(Event::Pressed);
+        }
+
+        if let (true, false, Some(long_press)) =
+            (self.pressed, self.long_press_sent, self.config.long_press)
+        {
+            if now - self.changed_at >= long_press {
+                self.long_press_sent = true;
+                return Some(Event::LongPress);
+            }
+        }
+        None
+    }
+
+    /// When an update is due after `now` even if the reading does not
+    /// change: the end of the debounce time or of a long press.
+    pub fn next_deadline(&self, now: Instant) -> Option<Instant> {
+        if self.pending.is_some() {
+            return Some(now);
+        }
+        let debounce_end = self.changed_at + self.config.debounce;
+        let long_press_end = match (self.pressed, self.long_press_sent, self.config.long_press) {
+            (true, false, Some(long_press)) => Some(self.changed_at + long_press),
+            _ => None,
+        };
+        [Some(debounce_end), long_press_end]
+            .into_iter()
+            .flatten()
+            .filter(|deadline| *deadline > now)
+            .min()
+    }
+}
+
+pub struct Switch<'d> {
+    input: ExtiInput<'d>,
+    state: SwitchState,
+}
+
+impl<'d> Switch<'d> {
+    /// `channel` is the EXTI line of the pin, e.g. `p.EXTI3` for `PA3`.
+    pub fn new<P: Pin>(
+        pin: impl Peripheral<P = P> + 'd,
+        channel: impl Peripheral<P = P::ExtiChannel> + 'd,
+        config: SwitchConfig,
+    ) -> Self {
+        Self {
+            input: ExtiInput::new(pin, channel, config.pull),
+            state: SwitchState::new(config),
+        }
+    }
+
+    /// Debounced state, as of the last update.
+    pub fn is_pressed(&self) -> bool {
+        self.state.is_pressed()
+    }
+
+    pub fn pressed_for(&self) -> Option<Duration> {
+        self.state.pressed_for(Instant::now())
+    }
+
+    /// Read the pin and get the next event, without waiting.
+    pub fn update(&mut self) -> Option<Event> {
+        let pressed = self.input.is_high() != self.state.config.active_low;
+        self.state.update(pressed, Instant::now())
+    }
+
+    pub async fn wait_for_event(&mut self) -> Event {
+        loop {
+            if let Some(event) = self.update() {
+                return event;
+            }
+            let now = Instant::now();
+            let deadline = self.state.next_deadline(now).unwrap_or(now + IDLE_POLL);
+            select(self.input.wait_for_any_edge(), Timer::at(deadline)).await;
+        }
+    }
+
+    pub async fn wait_for_press(&mut self) {
+        while self.wait_for_event().await != Event::Pressed {}
+    }
+
+    /// Wait for the switch to be released, returning how long it was held.
+    pub async fn wait_for_release(&mut self) -> Duration {
+        loop {
+            if let Event::Released { held } = self.wait_for_event().await {
+                return held;
+            }
+        }
+    }
+
+    pub fn state(&self) -> &SwitchState {
+        &self.state
+    }
+}
+
+/// A gate or trigger input. Most input circuits invert the signal, which
+/// `inverted` undoes.
+pub struct GateIn<'d>(Switch<'d>);
+
+impl<'d> GateIn<'d> {
+    pub fn new<P: Pin>(
+        pin: impl Peripheral<P = P> + 'd,
+        channel: impl Peripheral<P = P::ExtiChannel> + 'd,
+        inverted: bool,
+    ) -> Self {
+        let config = SwitchConfig {
+            pull: Pull::None,
+            active_low: inverted,
+            debounce: Duration::from_micros(500),
+            long_press: None,
+            double_press: None,
+        };
+        Self(Switch::new(pin, channel, config))
+    }
+
+    /// Debounced state, as of the last update.
+    pub fn is_high(&self) -> bool {
+        self.0.is_pressed()
+    }
+
+    /// Read the pin and get the next edge, without waiting.
+    pub fn update(&mut self) -> Option<Edge> {
+        self.0.update().and_then(Self::edge)
+    }
+
+    pub async fn wait_for_edge(&mut self) -> Edge {
+        loop {
+            if let Some(edge) = Self::edge(self.0.wait_for_event().await) {
+                return edge;
+            }
+        }
+    }
+
+    pub async fn wait_for_rising_edge(&mut self) {
+        while self.wait_for_edge().await != Edge::Rising {}
+    }
+
+    pub async fn wait_for_falling_edge(&mut self) {
+        while self.wait_for_edge().await != Edge::Falling {}
+    }
+
+    fn edge(event: Event) -> Option<Edge> {
+        match event {
+            Event::Pressed => Some(Edge::Rising),
+            Event::Released { .. } => Some(Edge::Falling),
+            _ => None,
+        }
+    }
+}
+
+/// The on-board "BOOT" button.
+///
+/// It shares EXTI3 with D16, so it is polled instead of using an interrupt.
+pub struct BootButton<'d> {
+    input: Input<'d>,
+    state: SwitchState,
+}
+
+impl<'d> BootButton<'d> {
+    pub fn new(pin: Boot) -> Self {
+        // pressing the button pulls BOOT0 and this pin high
+        let config = SwitchConfig {
+            pull: Pull::Down,
+            active_low: false,
+            ..Default::default()
+        };
+        Self {
+            input: Input::new(pin, config.pull),
+            state: SwitchState::new(config),
+        }
+    }
+
+    /// Debounced state, as of the last update.
+    pub fn is_pressed(&self) -> bool {
+        self.state.is_pressed()
+    }
+
+    /// Read the pin and get the next event, without waiting.
+    pub fn update(&mut self) -> Option<Event> {
+        self.state.update(self.input.is_high(), Instant::now())
+    }
+
+    pub async fn wait_for_event(&mut self) -> Event {
+        let mut ticker = Ticker::every(BOOT_POLL);
+        loop {
+            if let Some(event) = self.update() {
+                return event;
+            }
+            ticker.next().await;
+        }
+    }
+
+    /// Start the ST ROM bootloader if the button is held right now, e.g.
+    /// early at startup.
+    pub fn enter_bootloader_if_held(&self) {
+        if self.input.is_high() {
+            crate::system::reset_to_bootloader();
+        }
+    }
+
+    /// Start the ST ROM bootloader once the button is held for
+    /// [`SwitchConfig::long_press`]. Run this in its own task.
+    pub async fn enter_bootloader_on_long_press(&mut self) -> ! {
+        while self.wait_for_event().await != Event::LongPress {}
+        crate::system::reset_to_bootloader()
+    }
+}
@@ -0,0 +1,117 @@
+//! System level control of the STM32H750.
+
+use crate::update::boot;
+use cortex_m::peripheral::Peripherals;
+use embassy_stm32 as hal;
+use hal::pac::rcc::{regs, vals};
+use hal::pac::RCC;
+
+/// Start of the system memory holding the ST ROM bootloader of the
+/// STM32H750, see AN2606.
+pub const SYSTEM_MEMORY_ADDRESS: u32 = 0x1FF0_9800;
+
+// Reset bits defined in the RCC reset registers of the STM32H750, see
+// RM0433. The others are reserved and must keep their reset value.
+const AHB1_RESETS: u32 = 0x0A00_8023;
+const AHB2_RESETS: u32 = 0x0000_0271;
+const AHB3_RESETS: u32 = 0x0001_5031;
+const AHB4_RESETS: u32 = 0x0328_07FF;
+const APB1L_RESETS: u32 = 0xE8FF_C3FF;
+const APB1H_RESETS: u32 = 0x0000_0136;
+const APB2_RESETS: u32 = 0x31D7_3033;
+const APB3_RESETS: u32 = 0x0000_0008;
+const APB4_RESETS: u32 = 0x0020_DEAA;
+
+/// QUADSPIRST in RCC_AHB3RSTR.
+#[cfg(feature = "boot_qspi")]
+const AHB3_QSPI_RESET: u32 = 1 << 14;
+/// GPIOFRST and GPIOGRST in RCC_AHB4RSTR, the ports of the QSPI flash pins.
+#[cfg(feature = "boot_qspi")]
+const AHB4_QSPI_PINS_RESET: u32 = 1 << 5 | 1 << 6;
+
+/// Start the ST ROM bootloader, which shows up as a DFU device on the USB
+/// port, so the board can be flashed without a debugger, e.g. with
+/// `dfu-util`. Call it from a USB command or a button combination.
+///
+/// The bootloader expects the chip as it comes out of reset, so first all
+/// peripherals are reset, which also stops any DMA and disconnects USB.
+/// With `boot_qspi` the QUADSPI and the GPIO ports F and G are kept, as the
+/// program is still running from the flash behind them. Then the clocks
+/// are switched back to the HSI with the PLLs off, and the MPU and caches
+/// are disabled.
+pub fn reset_to_bootloader() -> ! {
+    cortex_m::interrupt::disable();
+    reset_peripherals();
+    reset_clocks();
+    unsafe {
+        let cp = Peripherals::steal();
+        cp.MPU.ctrl.write(0);
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+        boot::jump(SYSTEM_MEMORY_ADDRESS)
+    }
+}
+
+/// Pulse the reset of every peripheral on the AHB and APB buses.
+fn reset_peripherals() {
+    // the program runs from the QSPI flash, which must stay mapped, and so
+    // must the pins it is read through
+    #[cfg(feature = "boot_qspi")]
+    let (ahb3, ahb4) = (
+        AHB3_RESETS & !AHB3_QSPI_RESET,
+        AHB4_RESETS & !AHB4_QSPI_PINS_RESET,
+    );
+    #[cfg(not(feature = "boot_qspi"))]
+    let (ahb3, ahb4) = (AHB3_RESETS, AHB4_RESETS);
+
+    RCC.ahb1rstr().write_value(regs::Ahb1rstr(AHB1_RESETS));
+    RCC.ahb2rstr().write_value(regs::Ahb2rstr(AHB2_RESETS));
+    RCC.ahb3rstr().write_value(regs::Ahb3rstr(ahb3));
+    RCC.ahb4rstr().write_value(regs::Ahb4rstr(ahb4));
+    RCC.apb1lrstr().write_value(regs::Apb1lrstr(APB1L_RESETS));
+    RCC.apb1hrstr().write_value(regs::Apb1hrstr(APB1H_RESETS));
+    RCC.apb2rstr().write_value(regs::Apb2rstr(APB2_RESETS));
+    RCC.apb3rstr().write_value(regs::Apb3rstr(APB3_RESETS));
+    RCC.apb4rstr().write_value(regs::Apb4rstr(APB4_RESETS));
+
+    RCC.ahb1rstr().write_value(regs::Ahb1rstr(0));
+    RCC.ahb2rstr().write_value(regs::Ahb2rstr(0));
+    RCC.ahb3rstr().write_value(regs::Ahb3rstr(0));
+    RCC.ahb4rstr().write_value(regs::Ahb4rstr(0));
+    RCC.apb1lrstr().write_value(regs::Apb1lrstr(0));
+    RCC.apb1hrstr().write_value(regs::Apb1hrstr(0));
+    RCC.apb2rstr().write_value(regs::Apb2rstr(0));
+    RCC.apb3rstr().write_value(regs::Apb3rstr(0));
+    RCC.apb4rstr().write_value(regs::Apb4rstr(0));
+}
+
+/// Run from the 64 MHz HSI with no prescalers, and stop the HSE and PLLs,
+/// as after reset. The flash wait states are left as they are, which is
+/// safe at any lower clock.
+fn reset_clocks() {
+    RCC.cr().modify(|w| w.set_hsion(true));
+    while !RCC.cr().read().hsirdy() {}
+    RCC.cfgr().modify(|w| w.set_sw(vals::Sw::HSI));
+    while RCC.cfgr().read().sws() != vals::Sw::HSI {}
+
+    RCC.d1cfgr().write_value(regs::D1cfgr(0));
+    RCC.d2cfgr().write_value(regs::D2cfgr(0));
+    RCC.d3cfgr().write_value(regs::D3cfgr(0));
+    RCC.cfgr().write_value(regs::Cfgr(0));
+    RCC.cier().write_value(regs::Cier(0));
+
+    RCC.cr().modify(|w| {
+        w.set_hsidiv(vals::Hsidiv::DIV1);
+        w.set_hseon(false);
+        w.set_hsebyp(false);
+        w.set_hsecsson(false);
+        w.set_csion(false);
+        w.set_hsi48on(false);
+        for pll in 0..3 {
+            w.set_pllon(pll, false);
+        }
+    });
+    for pll in 0..3 {
+        while RCC.cr().read().pllrdy(pll) {}
+    }
+}
@@ -0,0 +1,104 @@
+//! Boot stage side of the update process.
+//!
+//! The boot stage is a small firmware in the internal flash. It builds the
+//! board, picks the image to run and starts it from RAM:
+//!
+//! ```ignore
+//! let mut updater = Updater::new(board.flash.build(), Layout::DEFAULT)?;
+//! match boot::select(&mut updater)? {
+//!     Some((slot, header)) => unsafe { boot::start(&mut updater, slot, &header) },
+//!     None => { /* nothing to boot, e.g. wait for an image over USB */ }
+//! }
+//! ```
+//!
+//! Images are plain binaries linked for their load address, with the vector
+//! table at the start, e.g. `cargo objcopy --release -- -O binary app.bin`.
+
+use super::{BootState, Error, ImageHeader, Slot, Updater};
+use crate::flash::Storage;
+use cortex_m::peripheral::{Peripherals, SCB};
+
+/// Interrupt enable and pending registers of the NVIC, enough for the 150
+/// interrupts of the H750.
+const NVIC_REGISTERS: usize = 5;
+
+/// Pick the image to start and record the decision.
+///
+/// - A pending image is started once on trial.
+/// - A pending image still on trial was not confirmed by the application,
+///   so it is dropped and the active image is started again.
+/// - Images failing their CRC check are never started.
+///
+/// `None` if there is no image to start.
+pub fn select<S: Storage>(updater: &mut Updater<S>) -> Result<Option<(Slot, ImageHeader)>, Error> {
+    let state = updater.boot_state()?;
+    if let Some(pending) = state.pending {
+        if !state.trial {
+            if let Some(header) = updater.image(pending) {
+                updater.write_boot_state(&BootState {
+                    trial: true,
+                    ..state
+                })?;
+                return Ok(Some((pending, header)));
+            }
+        }
+        // either the trial failed or the image is broken: roll back
+        updater.write_boot_state(&BootState {
+            pending: None,
+            trial: false,
+            ..state
+        })?;
+    }
+    Ok(state
+        .active
+        .and_then(|slot| updater.image(slot).map(|header| (slot, header))))
+}
+
+/// Copy the image in `slot` to its load address and jump to it.
+///
+/// # Safety
+///
+/// `header` must come from [`select`] or [`Updater::image`] for `slot`. The
+/// image overwrites the RAM at its load address, which must not be used by
+/// the boot stage itself, and it must be linked to run from there.
+pub unsafe fn start<S: Storage>(updater: &mut Updater<S>, slot: Slot, header: &ImageHeader) -> ! {
+    let destination =
+        core::slice::from_raw_parts_mut(header.load_address as *mut u8, header.length as usize);
+    let address = updater.image_address(slot);
+    updater.state.storage_mut().read(address, destination);
+    jump(header.load_address)
+}
+
+/// Hand the core over to the image whose vector table is at
+/// `vector_table`, as if it had been reset into.
+///
+/// # Safety
+///
+/// A valid vector table must be at `vector_table`. Peripherals keep their
+/// state, so the image has to configure everything it uses from scratch.
+pub unsafe fn jump(vector_table: u32) -> ! {
+    cortex_m::interrupt::disable();
+    let mut cp = Peripherals::steal();
+
+    cp.SYST.disable_interrupt();
+    cp.SYST.disable_counter();
+    for i in 0..NVIC_REGISTERS {
+        cp.NVIC.icer[i].write(0xFFFF_FFFF);
+        cp.NVIC.icpr[i].write(0xFFFF_FFFF);
+    }
+
+    // the copied image may still sit in the data cache
+    if SCB::dcache_enabled() {
+        cp.SCB.disable_dcache(&mut cp.CPUID);
+    }
+    if SCB::icache_enabled() {
+        cp.SCB.disable_icache();
+    }
+
+    cp.SCB.vtor.write(vector_table);
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+    // nothing is left to fire, and a reset would leave interrupts enabled too
+    cortex_m::interrupt::enable();
+    cortex_m::asm::bootload(vector_table as *const u32)
+}
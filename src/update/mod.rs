@@ -0,0 +1,429 @@
+//! Staged firmware updates in the QSPI flash.
+//!
+//! The H750 only has 128K of internal flash, so bigger applications are kept
+//! in one of two slots of the QSPI flash and copied into RAM at boot by a
+//! small boot stage living in internal flash (see [`boot`]).
+//!
+//! An update goes through these steps:
+//!
+//! 1. The running application writes the new image into the slot that does
+//!    not hold the active image ([`Updater::begin`], [`Updater::write`]).
+//! 2. [`Updater::finish`] checks the written data against the expected CRC
+//!    and stores the image header.
+//! 3. [`Updater::mark_pending`] asks the boot stage to try the new image on
+//!    the next reset.
+//! 4. The boot stage starts the pending image once, as a trial. If the new
+//!    application calls [`Updater::confirm`], the image becomes active.
+//!    Otherwise the next reset rolls back to the previous image.
+//!
+//! The boot state is kept in a [`Settings`] store, so it survives power
+//! losses at any point.
+
+pub mod boot;
+
+use crate::crc::Crc32;
+use crate::flash::{Storage, PAGE_SIZE, SECTOR_SIZE};
+use crate::settings::{self, Settings};
+
+const MAGIC: u32 = 0x474D_4944; // "DIMG"
+const HEADER_SIZE: usize = 20;
+/// Image data starts one page into the slot, after the header.
+const IMAGE_OFFSET: u32 = PAGE_SIZE;
+const STATE_KEY: u16 = 0x0001;
+const STATE_SIZE: u32 = 2 * SECTOR_SIZE;
+const NO_SLOT: u8 = 0xFF;
+const CHUNK_SIZE: usize = 64;
+
+/// Where images are copied to and started from by default: the start of
+/// the AXI SRAM, as in libDaisy's `BOOT_SRAM` model. Applications built with
+/// the `boot_sram` feature are linked for this address.
+pub const SRAM_LOAD_ADDRESS: u32 = 0x2400_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// The layout overlaps itself or does not fit into the storage.
+    InvalidLayout,
+    /// The image does not fit into a slot.
+    TooLarge,
+    /// [`Updater::write`] or [`Updater::finish`] was called without
+    /// [`Updater::begin`], or outside of the announced length.
+    NotStarted,
+    /// The written data does not match the expected CRC.
+    Verification,
+    /// The slot does not hold a complete image.
+    NoImage,
+    /// The running image is still on trial and must be confirmed before
+    /// another update.
+    Unconfirmed,
+    State(settings::Error),
+}
+
+impl From<settings::Error> for Error {
+    fn from(error: settings::Error) -> Self {
+        Self::State(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn other(self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Self::A => 0,
+            Self::B => 1,
+        }
+    }
+
+    fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(Self::A),
+            1 => Some(Self::B),
+            _ => None,
+        }
+    }
+}
+
+/// Placement of the update slots and boot state in the flash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Layout {
+    /// Sector-aligned start addresses of the two slots.
+    pub slots: [u32; 2],
+    /// Size of each slot, including one page for the image header.
+    pub slot_size: u32,
+    /// Sector-aligned start of the two sectors holding the boot state.
+    pub state: u32,
+}
+
+impl Layout {
+    /// Two 512 KiB slots and the boot state in the upper part of the flash.
+    pub const DEFAULT: Self = Self {
+        slots: [0x60_0000, 0x68_0000],
+        slot_size: 0x8_0000,
+        state: 0x70_0000,
+    };
+
+    fn is_valid(&self, capacity: u32) -> bool {
+        let ranges = [
+            (self.slots[0], self.slot_size),
+            (self.slots[1], self.slot_size),
+            (self.state, STATE_SIZE),
+        ];
+        let aligned = ranges.iter().all(|(start, size)| {
+            start.is_multiple_of(SECTOR_SIZE) && size.is_multiple_of(SECTOR_SIZE)
+        });
+        let inside = ranges
+            .iter()
+            .all(|(start, size)| start.checked_add(*size).is_some_and(|end| end <= capacity));
+        // only once the ends are known not to overflow
+        let disjoint = || {
+            ranges.iter().enumerate().all(|(i, (a, a_size))| {
+                ranges[i + 1..]
+                    .iter()
+                    .all(|(b, b_size)| a + a_size <= *b || b + b_size <= *a)
+            })
+        };
+        aligned && inside && disjoint() && self.slot_size > IMAGE_OFFSET
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Which images the boot stage should start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct BootState {
+    /// Last confirmed image.
+    pub active: Option<Slot>,
+    /// Image waiting to be tried.
+    pub pending: Option<Slot>,
+    /// Whether the pending image has been started and not confirmed yet.
+    pub trial: bool,
+}
+
+impl BootState {
+    const EMPTY: Self = Self {
+        active: None,
+        pending: None,
+        trial: false,
+    };
+
+    fn encode(&self) -> [u8; 4] {
+        let index = |slot: Option<Slot>| slot.map_or(NO_SLOT, |slot| slot.index() as u8);
+        [index(self.active), index(self.pending), self.trial as u8, 0]
+    }
+
+    fn decode(raw: &[u8]) -> Self {
+        Self {
+            active: Slot::from_index(raw[0]),
+            pending: Slot::from_index(raw[1]),
+            trial: raw[2] == 1,
+        }
+    }
+}
+
+/// Description of a complete image in a slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ImageHeader {
+    pub length: u32,
+    pub crc: u32,
+    /// Address the image is copied to. Its vector table is expected there.
+    pub load_address: u32,
+}
+
+impl ImageHeader {
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut raw = [0; HEADER_SIZE];
+        raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[4..8].copy_from_slice(&self.length.to_le_bytes());
+        raw[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        raw[12..16].copy_from_slice(&self.load_address.to_le_bytes());
+        let header_crc = crate::crc::crc32(&raw[..16]);
+        raw[16..20].copy_from_slice(&header_crc.to_le_bytes());
+        raw
+    }
+
+    fn decode(raw: &[u8; HEADER_SIZE]) -> Option<Self> {
+        let word = |at: usize| u32::from_le_bytes([raw[at], raw[at + 1], raw[at + 2], raw[at + 3]]);
+        (word(0) == MAGIC && word(16) == crate::crc::crc32(&raw[..16])).then(|| Self {
+            length: word(4),
+            crc: word(8),
+            load_address: word(12),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Staging {
+    slot: Slot,
+    length: u32,
+    load_address: u32,
+}
+
+pub struct Updater<S> {
+    state: Settings<S>,
+    layout: Layout,
+    staging: Option<Staging>,
+    finished: Option<Slot>,
+}
+
+impl<S: Storage> Updater<S> {
+    pub fn new(storage: S, layout: Layout) -> Result<Self, Error> {
+        if !layout.is_valid(storage.capacity()) {
+            return Err(Error::InvalidLayout);
+        }
+        let state = Settings::new(storage, layout.state..layout.state + STATE_SIZE)?;
+        Ok(Self {
+            state,
+            layout,
+            staging: None,
+            finished: None,
+        })
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn boot_state(&mut self) -> Result<BootState, Error> {
+        let mut raw = [0; 4];
+        Ok(match self.state.read(STATE_KEY, &mut raw)? {
+            Some(4) => BootState::decode(&raw),
+            _ => BootState::EMPTY,
+        })
+    }
+
+    /// Largest image a slot can hold.
+    pub fn max_image_len(&self) -> u32 {
+        self.layout.slot_size - IMAGE_OFFSET
+    }
+
+    /// Erase the slot not holding the active image and prepare it for an
+    /// image of `length` bytes, to be started at `load_address`.
+    pub fn begin(&mut self, length: u32, load_address: u32) -> Result<Slot, Error> {
+        if length > self.max_image_len() {
+            return Err(Error::TooLarge);
+        }
+        let state = self.boot_state()?;
+        if state.trial {
+            return Err(Error::Unconfirmed);
+        }
+        let slot = state.active.map_or(Slot::A, Slot::other);
+        if state.pending == Some(slot) {
+            // the pending image is about to be overwritten
+            self.write_boot_state(&BootState {
+                pending: None,
+                ..state
+            })?;
+        }
+
+        let base = self.layout.slots[slot.index()];
+        let end = base + (IMAGE_OFFSET + length).next_multiple_of(SECTOR_SIZE);
+        let storage = self.state.storage_mut();
+        for sector in (base..end).step_by(SECTOR_SIZE as usize) {
+            storage.erase_sector(sector);
+        }
+        self.staging = Some(Staging {
+            slot,
+            length,
+            load_address,
+        });
+        self.finished = None;
+        Ok(slot)
+    }
+
+    /// Write a chunk of the image, `offset` bytes from its start.
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        let staging = self.staging.ok_or(Error::NotStarted)?;
+        let end = offset.checked_add(data.len() as u32);
+        if end.is_none_or(|end| end > staging.length) {
+            return Err(Error::NotStarted);
+        }
+        let address = self.image_address(staging.slot) + offset;
+        self.state.storage_mut().program(address, data);
+        Ok(())
+    }
+
+    /// Check the written image against `expected_crc` (CRC-32 of the whole
+    /// image, see [`crate::crc`]) and store its header.
+    pub fn finish(&mut self, expected_crc: u32) -> Result<Slot, Error> {
+        let staging = self.staging.take().ok_or(Error::NotStarted)?;
+        let base = self.layout.slots[staging.slot.index()];
+        let crc = image_crc(self.state.storage_mut(), base, staging.length);
+        if crc != expected_crc {
+            return Err(Error::Verification);
+        }
+        let header = ImageHeader {
+            length: staging.length,
+            crc,
+            load_address: staging.load_address,
+        };
+        self.state.storage_mut().program(base, &header.encode());
+        self.finished = Some(staging.slot);
+        Ok(staging.slot)
+    }
+
+    /// Ask the boot stage to try the image completed by the last
+    /// [`Updater::finish`] on the next reset.
+    pub fn mark_pending(&mut self) -> Result<(), Error> {
+        let slot = self.finished.ok_or(Error::NoImage)?;
+        let state = self.boot_state()?;
+        self.write_boot_state(&BootState {
+            pending: Some(slot),
+            trial: false,
+            ..state
+        })
+    }
+
+    /// Keep the image on trial. Call this once the new application has
+    /// checked it works; until then, every reset rolls back.
+    pub fn confirm(&mut self) -> Result<(), Error> {
+        let state = self.boot_state()?;
+        if let (true, Some(pending)) = (state.trial, state.pending) {
+            self.write_boot_state(&BootState {
+                active: Some(pending),
+                pending: None,
+                trial: false,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Header of the complete, CRC-checked image in `slot`.
+    pub fn image(&mut self, slot: Slot) -> Option<ImageHeader> {
+        let base = self.layout.slots[slot.index()];
+        let storage = self.state.storage_mut();
+        let mut raw = [0; HEADER_SIZE];
+        storage.read(base, &mut raw);
+        let header = ImageHeader::decode(&raw)?;
+        (header.length <= self.layout.slot_size - IMAGE_OFFSET
+            && image_crc(storage, base, header.length) == header.crc)
+            .then_some(header)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.state.into_inner()
+    }
+
+    fn write_boot_state(&mut self, state: &BootState) -> Result<(), Error> {
+        Ok(self.state.write(STATE_KEY, &state.encode())?)
+    }
+
+    fn image_address(&self, slot: Slot) -> u32 {
+        self.layout.slots[slot.index()] + IMAGE_OFFSET
+    }
+}
+
+fn image_crc<S: Storage>(storage: &mut S, base: u32, length: u32) -> u32 {
+    let mut crc = Crc32::new();
+    let mut chunk = [0; CHUNK_SIZE];
+    let mut offset = 0;
+    while offset < length {
+        let n = (length - offset).min(CHUNK_SIZE as u32) as usize;
+        storage.read(base + IMAGE_OFFSET + offset, &mut chunk[..n]);
+        crc.update(&chunk[..n]);
+        offset += n as u32;
+    }
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::MemFlash;
+
+    fn new(layout: Layout) -> Result<Updater<MemFlash<{ 6 * 4096 }>>, Error> {
+        Updater::new(MemFlash::new(), layout)
+    }
+
+    #[test]
+    fn rejects_invalid_layouts() {
+        const VALID: Layout = Layout {
+            slots: [0, 2 * SECTOR_SIZE],
+            slot_size: 2 * SECTOR_SIZE,
+            state: 4 * SECTOR_SIZE,
+        };
+        assert!(new(VALID).is_ok());
+
+        let invalid = [
+            Layout {
+                slots: [0, SECTOR_SIZE],
+                ..VALID
+            },
+            Layout {
+                state: 4 * SECTOR_SIZE + PAGE_SIZE,
+                ..VALID
+            },
+            Layout {
+                state: 5 * SECTOR_SIZE,
+                ..VALID
+            },
+            // ends past `u32::MAX`
+            Layout {
+                slots: [0, u32::MAX - SECTOR_SIZE + 1],
+                ..VALID
+            },
+            Layout {
+                slots: [0, 2 * SECTOR_SIZE],
+                slot_size: u32::MAX - SECTOR_SIZE + 1,
+                state: 4 * SECTOR_SIZE,
+            },
+        ];
+        for layout in invalid {
+            assert!(matches!(new(layout), Err(Error::InvalidLayout)));
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! 1V/oct pitch CV on the ADC pins.
+//!
+//! The input circuit of a Eurorack module scales, offsets and often inverts
+//! the CV before it reaches the ADC, so the readings are calibrated against
+//! two known voltages with a [`Calibrator`]. The resulting [`VOct`] is kept in
+//! flash with [`Settings`] and converts readings into notes and frequencies,
+//! cheap enough for the audio callback.
+//!
+//! ```ignore
+//! let mut settings = Settings::new(board.flash.build(), 0x7F_0000..0x80_0000)?;
+//! let voct = match VOct::load(&mut settings, KEY_VOCT)? {
+//!     Some(voct) => voct,
+//!     None => {
+//!         // patch 1V, press the button, patch 3V, press the button
+//!         let mut calibrator = Calibrator::new(1.0, 3.0);
+//!         let voct = loop {
+//!             calibrator.measure(CV.get(0));
+//!             if button_pressed() {
+//!                 if let Some(result) = calibrator.confirm() {
+//!                     break result?;
+//!                 }
+//!             }
+//!         };
+//!         voct.store(&mut settings, KEY_VOCT)?;
+//!         voct
+//!     }
+//! };
+//! // in the audio callback
+//! let frequency = voct.frequency(CV.get(0));
+//! ```
+
+use crate::control::Calibration;
+use crate::flash::Storage;
+use crate::settings::{self, Settings};
+
+/// Full scale of the ADC pins in volts.
+pub const ADC_VOLTS: f32 = 3.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// No reading was measured for one of the voltages.
+    NoReadings,
+    /// Both voltages read the same, the CV is not connected.
+    NoResponse,
+}
+
+/// Pitch CV calibration.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct VOct {
+    /// Readings to volts.
+    pub calibration: Calibration,
+    /// MIDI note played at 0V.
+    pub zero_volt_note: f32,
+}
+
+impl VOct {
+    /// Size of [`VOct::to_bytes`].
+    pub const SIZE: usize = Calibration::SIZE + 4;
+
+    /// Volts of a reading.
+    pub fn volts(&self, reading: f32) -> f32 {
+        self.calibration.apply(reading)
+    }
+
+    /// Fractional MIDI note of a reading.
+    pub fn note(&self, reading: f32) -> f32 {
+        self.zero_volt_note + self.volts(reading) * 12.0
+    }
+
+    /// Frequency in Hz of a reading, with A4 at 440 Hz.
+    pub fn frequency(&self, reading: f32) -> f32 {
+        note_to_frequency(self.note(reading))
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..Calibration::SIZE].copy_from_slice(&self.calibration.to_bytes());
+        bytes[Calibration::SIZE..].copy_from_slice(&self.zero_volt_note.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::SIZE {
+            return None;
+        }
+        let (calibration, note) = bytes.split_at(Calibration::SIZE);
+        let zero_volt_note = f32::from_le_bytes(note.try_into().ok()?);
+        Some(Self {
+            calibration: Calibration::from_bytes(calibration)?,
+            zero_volt_note: zero_volt_note.is_finite().then_some(zero_volt_note)?,
+        })
+    }
+
+    /// Load the calibration stored under `key`, `None` if there is none.
+    pub fn load<S: Storage>(
+        settings: &mut Settings<S>,
+        key: u16,
+    ) -> Result<Option<Self>, settings::Error> {
+        let mut bytes = [0; Self::SIZE];
+        Ok(match settings.read(key, &mut bytes)? {
+            Some(Self::SIZE) => Self::from_bytes(&bytes),
+            _ => None,
+        })
+    }
+
+    pub fn store<S: Storage>(
+        &self,
+        settings: &mut Settings<S>,
+        key: u16,
+    ) -> Result<(), settings::Error> {
+        settings.write(key, &self.to_bytes())
+    }
+}
+
+impl Default for VOct {
+    /// Readings straight from an ADC pin, 0V playing C1.
+    fn default() -> Self {
+        Self {
+            calibration: Calibration {
+                scale: ADC_VOLTS,
+                offset: 0.0,
+            },
+            zero_volt_note: 24.0,
+        }
+    }
+}
+
+/// Two-point calibration: average the readings of a first known voltage,
+/// confirm, then the same for a second one.
+pub struct Calibrator {
+    volts: [f32; 2],
+    sums: [f32; 2],
+    counts: [u32; 2],
+    point: usize,
+}
+
+impl Calibrator {
+    /// Calibrate with `low` and `high` volts, e.g. 1V and 3V. The further
+    /// apart, the more accurate.
+    pub fn new(low: f32, high: f32) -> Self {
+        Self {
+            volts: [low, high],
+            sums: [0.0; 2],
+            counts: [0; 2],
+            point: 0,
+        }
+    }
+
+    /// The voltage to patch now.
+    pub fn expected_volts(&self) -> f32 {
+        self.volts[self.point]
+    }
+
+    /// Add a reading of [`Calibrator::expected_volts`].
+    pub fn measure(&mut self, reading: f32) {
+        self.sums[self.point] += reading;
+        self.counts[self.point] += 1;
+    }
+
+    /// Finish the current voltage. Returns the result after the second one,
+    /// with 0V playing C1; `None` while the second one is still to come.
+    pub fn confirm(&mut self) -> Option<Result<VOct, Error>> {
+        if self.point == 0 {
+            self.point = 1;
+            return None;
+        }
+        if self.counts.contains(&0) {
+            return Some(Err(Error::NoReadings));
+        }
+        let low = self.sums[0] / self.counts[0] as f32;
+        let high = self.sums[1] / self.counts[1] as f32;
+        let calibration = Calibration::from_points((low, self.volts[0]), (high, self.volts[1]));
+        Some(
+            calibration
+                .ok_or(Error::NoResponse)
+                .map(|calibration| VOct {
+                    calibration,
+                    ..Default::default()
+                }),
+        )
+    }
+}
+
+/// Frequency in Hz of a fractional MIDI note, with A4 at 440 Hz.
+pub fn note_to_frequency(note: f32) -> f32 {
+    440.0 * exp2((note - 69.0) / 12.0)
+}
+
+/// `2^x`, within a few hundredths of a cent.
+fn exp2(x: f32) -> f32 {
+    let x = x.clamp(-126.0, 127.0);
+    let mut whole = x as i32;
+    if whole as f32 > x {
+        whole -= 1;
+    }
+    // 2^frac = e^(frac * ln 2), Taylor series up to the 6th power
+    let t = (x - whole as f32) * core::f32::consts::LN_2;
+    let fraction = 1.0
+        + t * (1.0
+            + t / 2.0 * (1.0 + t / 3.0 * (1.0 + t / 4.0 * (1.0 + t / 5.0 * (1.0 + t / 6.0)))));
+    f32::from_bits(((whole + 127) as u32) << 23) * fraction
+}
@@ -1,12 +1,46 @@
 #![no_std]
 #![no_main]
 
+#[cfg(test)]
+#[cortex_m_rt::pre_init]
+unsafe fn pre_init() {
+    daisy_embassy::memory::init_sections();
+}
+
+#[cfg(test)]
+daisy_embassy::itcm! {
+    fn itcm_add(a: u32, b: u32) -> u32 {
+        a.wrapping_add(b)
+    }
+}
+
+#[cfg(test)]
+daisy_embassy::dtcm! {
+    static DTCM_VALUE: u32 = 0xDA15_E000;
+}
+
+#[cfg(test)]
+daisy_embassy::dtcm_bss! {
+    static DTCM_ZEROED: [u32; 4];
+}
+
 #[cfg(test)]
 #[embedded_test::tests(executor = embassy_executor::Executor::new())]
 mod tests {
+    use daisy_embassy::crc::crc32;
     use daisy_embassy::default_rcc;
+    use daisy_embassy::encoder::{Acceleration, EncoderConfig, EncoderState};
+    use daisy_embassy::flash::{MemFlash, SECTOR_SIZE};
+    use daisy_embassy::gate::{Command, GateSchedule};
+    use daisy_embassy::led::Pattern;
+    use daisy_embassy::sdram::self_test::Config;
+    use daisy_embassy::settings::Settings;
+    use daisy_embassy::switch::{Event, SwitchConfig, SwitchState};
+    use daisy_embassy::update::{boot, Layout, Slot, Updater, SRAM_LOAD_ADDRESS};
+    use daisy_embassy::voct::{note_to_frequency, Calibrator, VOct};
     use daisy_embassy::DaisyBoard;
     use defmt_rtt as _;
+    use embassy_time::{Duration, Instant};
 
     // A init function which is called before every test
     #[init]
@@ -23,4 +57,195 @@ mod tests {
     fn first_test(_board: DaisyBoard<'static>) -> Result<(), &'static str> {
         Ok(())
     }
+
+    #[test]
+    fn code_and_data_run_from_the_tcm() {
+        let function = super::itcm_add as *const () as usize;
+        assert!(function < 0x1_0000, "not in the ITCM: {:#x}", function);
+        assert_eq!(super::itcm_add(core::hint::black_box(40), 2), 42);
+
+        let value = &super::DTCM_VALUE as *const u32 as usize;
+        assert!((0x2000_0000..0x2002_0000).contains(&value));
+        // read the memory rather than the constants the compiler knows
+        // SAFETY: both are valid statics
+        unsafe {
+            assert_eq!(core::ptr::read_volatile(&super::DTCM_VALUE), 0xDA15_E000);
+            assert_eq!(core::ptr::read_volatile(&super::DTCM_ZEROED), [0; 4]);
+        }
+    }
+
+    #[test]
+    fn update_rolls_back_unconfirmed_image() {
+        const LAYOUT: Layout = Layout {
+            slots: [0, 2 * SECTOR_SIZE],
+            slot_size: 2 * SECTOR_SIZE,
+            state: 4 * SECTOR_SIZE,
+        };
+        let image = [0x5A; 1000];
+        let install = |updater: &mut Updater<_>| {
+            let slot = updater
+                .begin(image.len() as u32, SRAM_LOAD_ADDRESS)
+                .unwrap();
+            updater.write(0, &image).unwrap();
+            updater.finish(crc32(&image)).unwrap();
+            updater.mark_pending().unwrap();
+            slot
+        };
+        let mut updater = Updater::new(MemFlash::<{ 6 * 4096 }>::new(), LAYOUT).unwrap();
+
+        assert_eq!(install(&mut updater), Slot::A);
+        assert_eq!(
+            boot::select(&mut updater).unwrap().map(|(slot, _)| slot),
+            Some(Slot::A)
+        );
+        updater.confirm().unwrap();
+
+        assert_eq!(install(&mut updater), Slot::B);
+        assert_eq!(
+            boot::select(&mut updater).unwrap().map(|(slot, _)| slot),
+            Some(Slot::B)
+        );
+        // reset without confirming
+        assert_eq!(
+            boot::select(&mut updater).unwrap().map(|(slot, _)| slot),
+            Some(Slot::A)
+        );
+    }
+
+    #[test]
+    fn sdram_self_test_passes_through_the_cache(board: DaisyBoard<'static>) {
+        let mut core = cortex_m::Peripherals::take().unwrap();
+        core.SCB.enable_dcache(&mut core.CPUID);
+        let mut sdram = board.sdram.build(&mut core.MPU, &mut core.SCB);
+        let report = sdram.self_test(&mut core.SCB, &Config::full(0..64 * 1024), |_| {});
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn voct_calibration_persists_and_tracks_pitch() {
+        // an inverting input stage: 0V reads 0.8, every volt 0.1 less
+        let mut calibrator = Calibrator::new(1.0, 3.0);
+        calibrator.measure(0.69);
+        calibrator.measure(0.71);
+        assert!(calibrator.confirm().is_none());
+        calibrator.measure(0.5);
+        let voct = calibrator.confirm().unwrap().unwrap();
+        assert!((voct.note(0.6) - 48.0).abs() < 1e-3);
+
+        const RANGE: core::ops::Range<u32> = 0..2 * SECTOR_SIZE;
+        let mut settings = Settings::new(MemFlash::<{ 2 * 4096 }>::new(), RANGE).unwrap();
+        assert_eq!(VOct::load(&mut settings, 1), Ok(None));
+        voct.store(&mut settings, 1).unwrap();
+        assert_eq!(VOct::load(&mut settings, 1), Ok(Some(voct)));
+
+        assert!((note_to_frequency(69.0) - 440.0).abs() < 0.01);
+        assert!((note_to_frequency(57.0) - 220.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn switch_debounces_and_detects_presses() {
+        let at = Instant::from_millis;
+        let mut switch = SwitchState::new(SwitchConfig::default());
+        assert_eq!(switch.update(true, at(1000)), Some(Event::Pressed));
+        // bounces right after the press are ignored
+        assert_eq!(switch.update(false, at(1005)), None);
+        assert_eq!(switch.update(true, at(1010)), None);
+        let held = Duration::from_millis(100);
+        assert_eq!(
+            switch.update(false, at(1100)),
+            Some(Event::Released { held })
+        );
+
+        assert_eq!(switch.update(true, at(1300)), Some(Event::Pressed));
+        assert_eq!(switch.update(true, at(1300)), Some(Event::DoublePress));
+        assert_eq!(switch.update(true, at(2000)), None);
+        assert_eq!(switch.update(true, at(2100)), Some(Event::LongPress));
+        assert_eq!(switch.update(true, at(2200)), None);
+    }
+
+    #[test]
+    fn encoder_counts_detents_and_accelerates() {
+        // one detent clockwise, resting with both pins high
+        const CLOCKWISE: [(bool, bool); 4] =
+            [(false, true), (false, false), (true, false), (true, true)];
+        let at = Instant::from_millis;
+        let mut encoder = EncoderState::new(EncoderConfig::default());
+        let turned = CLOCKWISE.map(|(a, b)| encoder.update(a, b, at(0)));
+        assert_eq!(turned, [0, 0, 0, 1]);
+        let turned: i32 = CLOCKWISE
+            .iter()
+            .rev()
+            .skip(1)
+            .chain(&[(true, true)])
+            .map(|&(a, b)| encoder.update(a, b, at(0)))
+            .sum();
+        assert_eq!(turned, -1);
+
+        // a missed edge still ends the detent at rest, and the next one
+        // counts from there
+        assert_eq!(encoder.update(false, true, at(0)), 0);
+        assert_eq!(encoder.update(true, false, at(0)), 0);
+        assert_eq!(encoder.update(true, true, at(0)), 1);
+        let turned = CLOCKWISE.map(|(a, b)| encoder.update(a, b, at(0)));
+        assert_eq!(turned, [0, 0, 0, 1]);
+
+        let mut encoder = EncoderState::new(EncoderConfig {
+            acceleration: Some(Acceleration::default()),
+            ..Default::default()
+        });
+        let mut detents = [0; 3];
+        for (i, detent) in detents.iter_mut().enumerate() {
+            *detent = CLOCKWISE
+                .iter()
+                .map(|&(a, b)| encoder.update(a, b, at(10 * i as u64)))
+                .sum();
+        }
+        assert_eq!(detents, [1, 4, 4]);
+    }
+
+    #[test]
+    fn gate_schedule_times_pulses() {
+        let at = Instant::from_millis;
+        let mut gate = GateSchedule::new();
+        let width = Duration::from_millis(5);
+        gate.command(Command::Trigger { at: at(10), width });
+        assert!(!gate.update(at(9)));
+        assert!(gate.update(at(10)));
+        assert_eq!(gate.next_deadline(), Some(at(15)));
+        assert!(!gate.update(at(15)));
+        assert_eq!(gate.next_deadline(), None);
+
+        // a re-trigger during a pulse goes low for a tick first
+        gate.command(Command::Trigger { at: at(20), width });
+        assert!(gate.update(at(20)));
+        gate.command(Command::Trigger { at: at(22), width });
+        assert!(!gate.update(at(22)));
+        let restart = at(22) + Duration::from_ticks(1);
+        assert_eq!(gate.next_deadline(), Some(restart));
+        assert!(gate.update(restart));
+        assert_eq!(gate.next_deadline(), Some(restart + width));
+
+        gate.command(Command::Set(true));
+        assert!(gate.update(at(100)));
+    }
+
+    #[test]
+    fn led_patterns_blink_in_time() {
+        let ms = Duration::from_millis;
+        let blink = Pattern::Blink {
+            on: ms(100),
+            off: ms(300),
+        };
+        assert_eq!(blink.brightness(ms(50)), 1.0);
+        assert_eq!(blink.brightness(ms(150)), 0.0);
+        assert_eq!(blink.brightness(ms(450)), 1.0);
+
+        // E (.) and T (-), 150 ms per unit, separated by a word gap
+        let morse = Pattern::Morse("E T");
+        let lit = [0, 8, 9, 10, 18];
+        for unit in 0..20 {
+            let on = morse.brightness(ms(unit * 150 + 75)) > 0.5;
+            assert_eq!(on, lit.contains(&unit));
+        }
+    }
 }
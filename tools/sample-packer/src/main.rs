@@ -0,0 +1,213 @@
+//! Pack WAV files into a sample image readable with
+//! `daisy_embassy::samples::SampleBank`.
+//!
+//! ```text
+//! sample-packer -o samples.bin kick.wav snare.wav pad.wav
+//! ```
+//!
+//! Entries are named after the file stem. Write the image to the QSPI flash
+//! at a sector-aligned address, e.g. with `Flash::write` from a small
+//! firmware that embeds it with `include_bytes!`.
+
+// The image layout is shared with the firmware side.
+#[path = "../../../src/crc.rs"]
+mod crc;
+// The decoding half is only used by the firmware and the tests.
+#[allow(dead_code)]
+#[path = "../../../src/samples/format.rs"]
+mod format;
+mod wav;
+
+use format::{Entry, ImageHeader, DATA_ALIGN};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Size of the QSPI flash on the Daisy Seed.
+const FLASH_SIZE: usize = 8 * 1024 * 1024;
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("error: {message}");
+            eprintln!("usage: sample-packer -o OUTPUT INPUT.wav...");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut output = None;
+    let mut inputs = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(PathBuf::from(args.next().ok_or("missing output path")?))
+            }
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+    let output = output.ok_or("missing -o OUTPUT")?;
+    if inputs.is_empty() {
+        return Err("no input files".into());
+    }
+
+    let mut samples = Vec::new();
+    for path in &inputs {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let wav = wav::parse(&bytes).map_err(|e| format!("{}: {e}", path.display()))?;
+        samples.push((name_of(path), wav));
+    }
+
+    let image = pack(&samples)?;
+    if image.len() > FLASH_SIZE {
+        return Err(format!(
+            "image is {} bytes, the flash only holds {FLASH_SIZE}",
+            image.len()
+        ));
+    }
+    std::fs::write(&output, &image).map_err(|e| format!("{}: {e}", output.display()))?;
+
+    for (name, wav) in &samples {
+        let frames = wav.data.len() / (wav.channels as usize * wav.bits_per_sample as usize / 8);
+        println!(
+            "{name:32} {:6} Hz {}ch {:2} bit {frames:8} frames{}",
+            wav.sample_rate,
+            wav.channels,
+            wav.bits_per_sample,
+            match wav.loop_points {
+                Some((start, end)) => format!(", loop {start}..{end}"),
+                None => String::new(),
+            }
+        );
+    }
+    println!("wrote {} bytes to {}", image.len(), output.display());
+    Ok(())
+}
+
+fn name_of(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn pack(samples: &[(String, wav::Wav)]) -> Result<Vec<u8>, String> {
+    let entry_count =
+        u16::try_from(samples.len()).map_err(|_| format!("too many samples: {}", samples.len()))?;
+    let mut header = ImageHeader {
+        entry_count,
+        directory_crc: 0,
+    };
+
+    let mut data = Vec::new();
+    let mut directory = Vec::new();
+    let mut offset = header.data_start();
+    for (name, wav) in samples {
+        offset = offset.next_multiple_of(DATA_ALIGN);
+        let start = offset as usize - header.data_start() as usize;
+        data.resize(start, 0);
+        data.extend_from_slice(&wav.data);
+        let length = u32::try_from(wav.data.len()).map_err(|_| format!("{name} is too large"))?;
+        let entry = Entry {
+            name: Entry::encode_name(name),
+            sample_rate: wav.sample_rate,
+            channels: wav.channels,
+            bits_per_sample: wav.bits_per_sample,
+            format: wav.format,
+            loop_points: wav.loop_points,
+            offset,
+            length,
+            crc: crc::crc32(&wav.data),
+        };
+        directory.push(entry.encode());
+        offset += length;
+    }
+    header.directory_crc = format::directory_crc(&directory);
+
+    let mut image = header.encode().to_vec();
+    for entry in &directory {
+        image.extend_from_slice(entry);
+    }
+    debug_assert_eq!(image.len(), header.data_start() as usize);
+    image.extend(data);
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use format::{SampleFormat, ENTRY_SIZE, HEADER_SIZE};
+
+    #[test]
+    fn packed_image_round_trips() {
+        let samples = vec![
+            (
+                "one".to_string(),
+                wav::Wav {
+                    sample_rate: 48_000,
+                    channels: 1,
+                    bits_per_sample: 24,
+                    format: SampleFormat::Int,
+                    loop_points: None,
+                    data: vec![0x00, 0x00, 0x80, 0xFF, 0xFF, 0x7F, 0x01],
+                },
+            ),
+            (
+                "two".to_string(),
+                wav::Wav {
+                    sample_rate: 44_100,
+                    channels: 2,
+                    bits_per_sample: 32,
+                    format: SampleFormat::Float,
+                    loop_points: Some((0, 1)),
+                    data: [0.5f32, -0.25]
+                        .iter()
+                        .flat_map(|x| x.to_le_bytes())
+                        .collect(),
+                },
+            ),
+        ];
+        let image = pack(&samples).unwrap();
+
+        let header = ImageHeader::decode(image[..HEADER_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(header.entry_count, 2);
+        let raw_entries: Vec<[u8; ENTRY_SIZE]> = image[HEADER_SIZE..header.data_start() as usize]
+            .chunks_exact(ENTRY_SIZE)
+            .map(|raw| raw.try_into().unwrap())
+            .collect();
+        assert_eq!(format::directory_crc(&raw_entries), header.directory_crc);
+
+        let one = Entry::decode(&raw_entries[0]).unwrap();
+        let two = Entry::decode(&raw_entries[1]).unwrap();
+        assert_eq!(one.name(), "one");
+        assert_eq!(one.frames(), 2);
+        assert_eq!(two.offset % DATA_ALIGN, 0);
+        assert_eq!(two.loop_points, Some((0, 1)));
+
+        let data = |entry: &Entry| &image[entry.offset as usize..][..entry.length as usize];
+        assert_eq!(crc::crc32(data(&one)), one.crc);
+        let mut out = [0.0; 4];
+        assert_eq!(format::decode_samples(&one, data(&one), &mut out), 2);
+        assert_eq!(out[..2], [-1.0, 8_388_607.0 / 8_388_608.0]);
+        assert_eq!(format::decode_samples(&two, data(&two), &mut out), 2);
+        assert_eq!(out[..2], [0.5, -0.25]);
+    }
+
+    #[test]
+    fn names_are_truncated_at_char_boundaries() {
+        let name = "ü".repeat(20);
+        let encoded = Entry::encode_name(&name);
+        let entry = Entry {
+            name: encoded,
+            sample_rate: 0,
+            channels: 1,
+            bits_per_sample: 8,
+            format: SampleFormat::Int,
+            loop_points: None,
+            offset: 0,
+            length: 0,
+            crc: 0,
+        };
+        assert_eq!(entry.name(), "ü".repeat(16));
+    }
+}
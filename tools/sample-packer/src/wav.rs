@@ -0,0 +1,164 @@
+//! Minimal RIFF/WAVE reader: PCM and IEEE float data plus the loop points of
+//! the `smpl` chunk.
+
+use crate::format::SampleFormat;
+
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Debug)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+    pub format: SampleFormat,
+    /// Loop start and end in frames, end exclusive.
+    pub loop_points: Option<(u32, u32)>,
+    pub data: Vec<u8>,
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Wav, String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a RIFF/WAVE file".into());
+    }
+
+    let mut fmt = None;
+    let mut data = None;
+    let mut loop_points = None;
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let id = &rest[0..4];
+        let size = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        let body = rest
+            .get(8..8 + size)
+            .ok_or_else(|| format!("truncated {} chunk", String::from_utf8_lossy(id)))?;
+        match id {
+            b"fmt " => fmt = Some(parse_fmt(body)?),
+            b"data" => data = Some(body.to_vec()),
+            b"smpl" => loop_points = parse_smpl(body),
+            _ => {}
+        }
+        // chunks are padded to an even size
+        let next = (8 + size + 1) & !1;
+        rest = rest.get(next..).unwrap_or(&[]);
+    }
+
+    let (format, channels, sample_rate, bits_per_sample) = fmt.ok_or("missing fmt chunk")?;
+    let data = data.ok_or("missing data chunk")?;
+    let frame = channels as usize * (bits_per_sample as usize / 8);
+    let frames = (data.len() / frame) as u32;
+    let loop_points = loop_points.filter(|&(start, end)| start < end && end <= frames);
+    Ok(Wav {
+        sample_rate,
+        channels,
+        bits_per_sample,
+        format,
+        loop_points,
+        data,
+    })
+}
+
+fn parse_fmt(body: &[u8]) -> Result<(SampleFormat, u8, u32, u8), String> {
+    if body.len() < 16 {
+        return Err("fmt chunk too short".into());
+    }
+    let u16_at = |at: usize| u16::from_le_bytes([body[at], body[at + 1]]);
+    let mut tag = u16_at(0);
+    if tag == FORMAT_EXTENSIBLE {
+        // the sub-format GUID starts with the actual format tag
+        let guid = body.get(24..26).ok_or("fmt chunk too short")?;
+        tag = u16::from_le_bytes([guid[0], guid[1]]);
+    }
+    let channels = u16_at(2);
+    let sample_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+    let bits_per_sample = u16_at(14);
+    let format = match (tag, bits_per_sample) {
+        (FORMAT_PCM, 8 | 16 | 24 | 32) => SampleFormat::Int,
+        (FORMAT_FLOAT, 32) => SampleFormat::Float,
+        _ => {
+            return Err(format!(
+                "unsupported format {tag:#06x} with {bits_per_sample} bits per sample"
+            ))
+        }
+    };
+    let channels = u8::try_from(channels)
+        .ok()
+        .filter(|channels| *channels > 0)
+        .ok_or_else(|| format!("unsupported channel count {channels}"))?;
+    Ok((format, channels, sample_rate, bits_per_sample as u8))
+}
+
+/// First loop of a `smpl` chunk. WAV stores the end frame inclusive.
+fn parse_smpl(body: &[u8]) -> Option<(u32, u32)> {
+    let u32_at = |at: usize| {
+        body.get(at..at + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    };
+    if u32_at(28)? == 0 {
+        return None;
+    }
+    // 36 bytes of sampler data, then 24 bytes per loop
+    let start = u32_at(36 + 8)?;
+    let end = u32_at(36 + 12)?;
+    Some((start, end.checked_add(1)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(fmt: &[u8], data: &[u8], smpl: Option<&[u8]>) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        for (id, body) in [(b"fmt ", fmt), (b"data", data)]
+            .into_iter()
+            .chain(smpl.map(|s| (b"smpl", s)))
+        {
+            chunks.extend_from_slice(id);
+            chunks.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(body);
+            if body.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        }
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        file.extend(chunks);
+        file
+    }
+
+    fn fmt(tag: u16, channels: u16, rate: u32, bits: u16) -> Vec<u8> {
+        let align = channels * bits / 8;
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&rate.to_le_bytes());
+        fmt.extend_from_slice(&(rate * align as u32).to_le_bytes());
+        fmt.extend_from_slice(&align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        fmt
+    }
+
+    #[test]
+    fn reads_pcm_with_loop() {
+        let mut smpl = vec![0; 36 + 24];
+        smpl[28..32].copy_from_slice(&1u32.to_le_bytes());
+        smpl[44..48].copy_from_slice(&1u32.to_le_bytes());
+        smpl[48..52].copy_from_slice(&2u32.to_le_bytes());
+        let data = [0u8; 4 * 2 * 2];
+        let wav = parse(&wav(&fmt(1, 2, 48_000, 16), &data, Some(&smpl))).unwrap();
+        assert_eq!(wav.sample_rate, 48_000);
+        assert_eq!(wav.channels, 2);
+        assert_eq!(wav.bits_per_sample, 16);
+        assert_eq!(wav.format, SampleFormat::Int);
+        assert_eq!(wav.loop_points, Some((1, 3)));
+        assert_eq!(wav.data.len(), 16);
+    }
+
+    #[test]
+    fn rejects_unsupported_format() {
+        assert!(parse(&wav(&fmt(2, 1, 48_000, 4), &[0; 4], None)).is_err());
+        assert!(parse(b"not a wav file").is_err());
+    }
+}